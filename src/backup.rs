@@ -0,0 +1,196 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::error::PatcherError;
+use crate::progress::ProgressEvent;
+
+const SNAPSHOT_FILE: &str = "snapshot.json";
+/// Where in the game directory snapshots are kept.
+pub const SNAPSHOT_DIR_NAME: &str = ".gyeet_backup";
+
+/// A copy of the files an update is about to modify, kept per game install
+/// so a failed update can be rolled back and a successful one undone.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    #[serde(skip)]
+    dir: PathBuf,
+    pub version: String,
+    pub created: u64,
+    files: Vec<SnapshotFile>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotFile {
+    original: PathBuf,
+    backup: String,
+}
+
+impl Snapshot {
+    /// Directory holding the snapshot for the install at `game_dir`. It is
+    /// inside the game directory, on the pak's filesystem, so backing up
+    /// can use a reflink and restoring a rename.
+    pub fn dir_for(game_dir: &Path) -> PathBuf {
+        game_dir.join(SNAPSHOT_DIR_NAME)
+    }
+
+    /// Where the snapshot goes when the game directory cannot hold it, and
+    /// where earlier versions of GYeet kept it.
+    fn fallback_dir_for(game_dir: &Path) -> PathBuf {
+        install_data_dir(game_dir, "backups")
+    }
+
+    /// Copies `files` aside, replacing any earlier snapshot of this install.
+    pub fn create<F>(
+        game_dir: &Path,
+        version: &str,
+        files: &[&Path],
+        output_callback: &mut F,
+    ) -> Result<Self, PatcherError>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        let fallback = Self::fallback_dir_for(game_dir);
+        let _ = fs::remove_dir_all(&fallback);
+        let mut dir = Self::dir_for(game_dir);
+        let _ = fs::remove_dir_all(&dir);
+        if let Err(e) = fs::create_dir_all(&dir) {
+            output_callback(ProgressEvent::Warning(format!(
+                "Cannot keep the backup in {} ({}), using {}",
+                dir.display(),
+                e,
+                fallback.display()
+            )));
+            dir = fallback;
+            fs::create_dir_all(&dir)
+                .map_err(PatcherError::io("Failed to create backup directory", &dir))?;
+        }
+
+        let mut snapshot = Snapshot {
+            dir,
+            version: version.to_string(),
+            created: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            files: Vec::new(),
+        };
+
+        for (index, original) in files.iter().enumerate() {
+            let backup = format!("{}.bak", index);
+            let method = clone_or_copy(original, &snapshot.dir.join(&backup)).map_err(|e| {
+                let _ = fs::remove_dir_all(&snapshot.dir);
+                PatcherError::io("Failed to back up", original)(e)
+            })?;
+            output_callback(ProgressEvent::info(format!(
+                "Backed up {} ({})",
+                original.display(),
+                method
+            )));
+            snapshot.files.push(SnapshotFile {
+                original: original.to_path_buf(),
+                backup,
+            });
+        }
+
+        let content =
+            serde_json::to_string_pretty(&snapshot).expect("snapshot metadata always serializes");
+        let snapshot_path = snapshot.dir.join(SNAPSHOT_FILE);
+        fs::write(&snapshot_path, content)
+            .map_err(PatcherError::io("Failed to write snapshot", &snapshot_path))?;
+
+        Ok(snapshot)
+    }
+
+    /// Loads the snapshot for the install at `game_dir`, if there is one.
+    pub fn load(game_dir: &Path) -> Option<Self> {
+        [Self::dir_for(game_dir), Self::fallback_dir_for(game_dir)]
+            .into_iter()
+            .find_map(|dir| {
+                let content = fs::read_to_string(dir.join(SNAPSHOT_FILE)).ok()?;
+                let mut snapshot: Snapshot = serde_json::from_str(&content).ok()?;
+                snapshot.dir = dir;
+                Some(snapshot)
+            })
+    }
+
+    /// Puts every backed up file back in place. The snapshot is kept so the
+    /// restore can be repeated.
+    pub fn restore<F>(&self, output_callback: &mut F) -> Result<(), PatcherError>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        for file in &self.files {
+            let backup = self.dir.join(&file.backup);
+            let mut staging = file.original.as_os_str().to_os_string();
+            staging.push(".gyeet_restore");
+            let staging = PathBuf::from(staging);
+
+            clone_or_copy(&backup, &staging)
+                .and_then(|_| fs::rename(&staging, &file.original))
+                .map_err(|e| {
+                    let _ = fs::remove_file(&staging);
+                    PatcherError::io("Failed to restore", &file.original)(e)
+                })?;
+            output_callback(ProgressEvent::info(format!(
+                "Restored {}",
+                file.original.display()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// GYeet's `kind` directory for the install at `game_dir`, such as its held
+/// mods, kept outside the game so it survives a reinstall.
+pub fn install_data_dir(game_dir: &Path, kind: &str) -> PathBuf {
+    let canonical = game_dir
+        .canonicalize()
+        .unwrap_or_else(|_| game_dir.to_path_buf());
+    let key = Sha256::digest(canonical.to_string_lossy().as_bytes());
+    let key: String = key.iter().take(8).map(|b| format!("{:02x}", b)).collect();
+
+    dirs::data_local_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("gyeet")
+        .join(kind)
+        .join(key)
+}
+
+/// Copies `src` to `dst`, sharing extents with a reflink when the filesystem
+/// supports it. Returns which method was used.
+pub fn clone_or_copy(src: &Path, dst: &Path) -> io::Result<&'static str> {
+    if reflink(src, dst).is_ok() {
+        return Ok("reflink");
+    }
+    fs::copy(src, dst)?;
+    Ok("copy")
+}
+
+#[cfg(target_os = "linux")]
+fn reflink(src: &Path, dst: &Path) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let src_file = fs::File::open(src)?;
+    let dst_file = fs::File::create(dst)?;
+    // SAFETY: both descriptors are open for the duration of the call.
+    let result = unsafe { libc::ioctl(dst_file.as_raw_fd(), libc::FICLONE, src_file.as_raw_fd()) };
+    if result == 0 {
+        return Ok(());
+    }
+
+    let err = io::Error::last_os_error();
+    drop(dst_file);
+    let _ = fs::remove_file(dst);
+    Err(err)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink(_src: &Path, _dst: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "reflink not supported",
+    ))
+}
@@ -0,0 +1,437 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::config::Config;
+use crate::error::PatcherError;
+
+/// Downloaded patches and indexes, stored under their SHA256 so a retry or
+/// a second install does not fetch them again. Entries are evicted least
+/// recently used first once the cache grows past its limit; a file's
+/// modification time records when it was last used.
+pub struct DownloadCache {
+    dir: PathBuf,
+    limit: u64,
+}
+
+/// How much space the cache takes up.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheUsage {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+struct Entry {
+    path: PathBuf,
+    size: u64,
+    used: SystemTime,
+}
+
+impl DownloadCache {
+    /// The cache in its default location, limited to the configured size.
+    pub fn new(config: &Config) -> Self {
+        DownloadCache {
+            dir: Self::default_dir(),
+            limit: config.download_cache_limit_mb.saturating_mul(1024 * 1024),
+        }
+    }
+
+    fn default_dir() -> PathBuf {
+        dirs::cache_dir()
+            .unwrap_or_else(std::env::temp_dir)
+            .join("gyeet")
+            .join("downloads")
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn contains(&self, sha256: &str) -> bool {
+        self.path_for(sha256).is_file()
+    }
+
+    /// Where the file with this SHA256 is, or is to be, stored.
+    pub fn path_for(&self, sha256: &str) -> PathBuf {
+        self.dir.join(sha256.to_uppercase())
+    }
+
+    /// The cached file with this SHA256, marked as just used. Callers should
+    /// still check the hash and `remove` the entry if it does not match.
+    pub fn lookup(&self, sha256: &str) -> Option<PathBuf> {
+        let path = self.path_for(sha256);
+        let file = fs::File::options().write(true).open(&path).ok()?;
+        let _ = file.set_modified(SystemTime::now());
+        Some(path)
+    }
+
+    pub fn remove(&self, sha256: &str) {
+        let _ = fs::remove_file(self.path_for(sha256));
+    }
+
+    /// Evicts the least recently used entries until the cache fits its
+    /// limit. `keep` is never evicted, even if it alone is over the limit.
+    pub fn evict(&self, keep: &str) -> CacheUsage {
+        let keep = self.path_for(keep);
+        let mut entries = self.entries();
+        entries.sort_by_key(|entry| entry.used);
+
+        let mut total: u64 = entries.iter().map(|entry| entry.size).sum();
+        let mut evicted = CacheUsage::default();
+        for entry in entries {
+            if total <= self.limit {
+                break;
+            }
+            if entry.path == keep {
+                continue;
+            }
+            if fs::remove_file(&entry.path).is_ok() {
+                total -= entry.size;
+                evicted.files += 1;
+                evicted.bytes += entry.size;
+            }
+        }
+        evicted
+    }
+
+    pub fn usage(&self) -> CacheUsage {
+        let entries = self.entries();
+        CacheUsage {
+            files: entries.len(),
+            bytes: entries.iter().map(|entry| entry.size).sum(),
+        }
+    }
+
+    /// Deletes everything in the cache, including unfinished downloads.
+    pub fn clear(&self) -> Result<CacheUsage, PatcherError> {
+        let mut cleared = CacheUsage::default();
+        let dir = match fs::read_dir(&self.dir) {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(cleared),
+            Err(e) => return Err(PatcherError::io("Failed to read", &self.dir)(e)),
+        };
+        for item in dir.flatten() {
+            let path = item.path();
+            let size = item.metadata().map(|m| m.len()).unwrap_or(0);
+            fs::remove_file(&path).map_err(PatcherError::io("Failed to delete", &path))?;
+            cleared.files += 1;
+            cleared.bytes += size;
+        }
+        Ok(cleared)
+    }
+
+    /// Complete entries; `.part` files of running downloads are left alone.
+    fn entries(&self) -> Vec<Entry> {
+        let Ok(dir) = fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        dir.flatten()
+            .filter(|item| Self::is_entry(&item.path()))
+            .filter_map(|item| {
+                let metadata = item.metadata().ok()?;
+                Some(Entry {
+                    path: item.path(),
+                    size: metadata.len(),
+                    used: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                })
+            })
+            .collect()
+    }
+
+    fn is_entry(path: &Path) -> bool {
+        path.is_file() && path.extension().is_none()
+    }
+}
+
+/// Compressed chunks fetched from the chunk stores, laid out like a desync
+/// local store (`<first 4 hex digits>/<chunk id>.cacnk`), so the directory
+/// also works as a desync cache. Chunks are checked against their ID when
+/// read, like those from a remote store. Like the download cache, it is
+/// kept to a size limit by evicting the least recently used chunks, checked
+/// every `EVICT_INTERVAL` bytes inserted so a large install cannot outgrow it.
+pub struct ChunkCache {
+    dir: PathBuf,
+    limit: u64,
+    /// Bytes inserted since the size was last checked.
+    unchecked: AtomicU64,
+    /// Everything evicted so far. Locked while evicting, so inserts from
+    /// several threads do not evict at once.
+    evicted: Mutex<CacheUsage>,
+}
+
+/// How many bytes go into the chunk cache between checks of its size.
+const EVICT_INTERVAL: u64 = 64 * 1024 * 1024;
+
+impl ChunkCache {
+    /// The configured chunk cache, or None if it is disabled.
+    pub fn new(config: &Config) -> Option<Self> {
+        let dir = config.chunk_cache_dir.trim();
+        (!dir.is_empty()).then(|| ChunkCache {
+            dir: PathBuf::from(dir),
+            limit: config.chunk_cache_limit_mb.saturating_mul(1024 * 1024),
+            unchecked: AtomicU64::new(0),
+            evicted: Mutex::new(CacheUsage::default()),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The size limit in bytes.
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    fn path_for(&self, id: &str) -> PathBuf {
+        self.dir.join(&id[..4]).join(format!("{}.cacnk", id))
+    }
+
+    /// The compressed chunk with this ID, if it is cached, marked as just
+    /// used.
+    pub fn get(&self, id: &str) -> Option<Vec<u8>> {
+        let path = self.path_for(id);
+        let data = fs::read(&path).ok()?;
+        if let Ok(file) = fs::File::options().write(true).open(&path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+        Some(data)
+    }
+
+    /// Stores a compressed chunk. Written under a temporary name and
+    /// renamed, so a reader never sees half a chunk. Evicts old chunks once
+    /// enough has been inserted that the cache may be over its limit.
+    pub fn insert(&self, id: &str, compressed: &[u8]) -> std::io::Result<()> {
+        let path = self.path_for(id);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+        fs::write(&temp_path, compressed)
+            .and_then(|()| fs::rename(&temp_path, &path))
+            .inspect_err(|_| {
+                let _ = fs::remove_file(&temp_path);
+            })?;
+
+        let size = compressed.len() as u64;
+        if self.unchecked.fetch_add(size, Ordering::Relaxed) + size >= EVICT_INTERVAL {
+            self.unchecked.store(0, Ordering::Relaxed);
+            self.evict();
+        }
+        Ok(())
+    }
+
+    pub fn remove(&self, id: &str) {
+        let _ = fs::remove_file(self.path_for(id));
+    }
+
+    pub fn usage(&self) -> CacheUsage {
+        let chunks = self.chunks(false);
+        CacheUsage {
+            files: chunks.len(),
+            bytes: chunks.iter().map(|chunk| chunk.size).sum(),
+        }
+    }
+
+    /// Evicts the least recently used chunks until the cache fits its limit.
+    /// Returns everything evicted since the cache was opened, including by
+    /// `insert`.
+    pub fn evict(&self) -> CacheUsage {
+        let mut evicted = self.evicted.lock().unwrap_or_else(|e| e.into_inner());
+        let mut chunks = self.chunks(false);
+        chunks.sort_by_key(|chunk| chunk.used);
+
+        let mut total: u64 = chunks.iter().map(|chunk| chunk.size).sum();
+        for chunk in chunks {
+            if total <= self.limit {
+                break;
+            }
+            if Self::delete(&chunk.path).is_ok() {
+                total -= chunk.size;
+                evicted.files += 1;
+                evicted.bytes += chunk.size;
+            }
+        }
+        *evicted
+    }
+
+    /// Deletes every chunk whose ID is not in `keep`, along with this
+    /// process's leftovers of interrupted writes, like `desync prune` given
+    /// the indexes the IDs come from.
+    pub fn prune(&self, keep: &HashSet<String>) -> Result<CacheUsage, PatcherError> {
+        let mut pruned = CacheUsage::default();
+        for chunk in self.chunks(true) {
+            let path = &chunk.path;
+            let id = path.file_stem().and_then(|stem| stem.to_str());
+            let is_chunk = path.extension().is_some_and(|e| e == "cacnk");
+            if is_chunk && id.is_some_and(|id| keep.contains(id)) {
+                continue;
+            }
+            Self::delete(path).map_err(PatcherError::io("Failed to delete", path))?;
+            pruned.files += 1;
+            pruned.bytes += chunk.size;
+        }
+        Ok(pruned)
+    }
+
+    fn delete(path: &Path) -> std::io::Result<()> {
+        fs::remove_file(path)?;
+        // Only succeeds once the prefix directory is empty
+        if let Some(prefix) = path.parent() {
+            let _ = fs::remove_dir(prefix);
+        }
+        Ok(())
+    }
+
+    /// The `<id>.cacnk` files in the cache's prefix directories, and with
+    /// `leftovers` also the `<id>.<pid>.tmp` files this process left there.
+    /// Nothing else is listed, so files that merely share the directory are
+    /// never evicted or pruned.
+    fn chunks(&self, leftovers: bool) -> Vec<Entry> {
+        let Ok(prefixes) = fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        let leftover_suffix = format!(".{}.tmp", std::process::id());
+        let mut chunks = Vec::new();
+        for prefix in prefixes.flatten() {
+            let prefix_name = prefix.file_name();
+            let Some(prefix_name) = prefix_name.to_str().filter(|name| is_hex(name, 4)) else {
+                continue;
+            };
+            let Ok(files) = fs::read_dir(prefix.path()) else {
+                continue;
+            };
+            for file in files.flatten() {
+                let name = file.file_name();
+                let Some(name) = name.to_str() else {
+                    continue;
+                };
+                let is_chunk = |suffix: &str| {
+                    name.strip_suffix(suffix)
+                        .is_some_and(|id| is_hex(id, 64) && id.starts_with(prefix_name))
+                };
+                if !(is_chunk(".cacnk") || leftovers && is_chunk(&leftover_suffix)) {
+                    continue;
+                }
+                let Ok(metadata) = file.metadata() else {
+                    continue;
+                };
+                if metadata.is_file() {
+                    chunks.push(Entry {
+                        path: file.path(),
+                        size: metadata.len(),
+                        used: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                    });
+                }
+            }
+        }
+        chunks
+    }
+}
+
+/// Whether `name` is `len` lowercase hex digits, as chunk IDs and prefix
+/// directories are named.
+fn is_hex(name: &str, len: usize) -> bool {
+    name.len() == len && name.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A chunk cache in a scratch directory, limited to `limit_mb`.
+    fn setup(name: &str, limit_mb: u64) -> ChunkCache {
+        let dir = std::env::temp_dir().join(format!("gyeet-cache-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let config = Config {
+            chunk_cache_dir: dir.display().to_string(),
+            chunk_cache_limit_mb: limit_mb,
+            ..Config::default()
+        };
+        ChunkCache::new(&config).unwrap()
+    }
+
+    fn id(n: u64) -> String {
+        format!("{:064x}", 0xabcd_0000 + n)
+    }
+
+    /// Files that are not the cache's, including one in a directory named
+    /// like a prefix.
+    fn add_foreign_files(cache: &ChunkCache) -> Vec<PathBuf> {
+        let files = vec![
+            cache.dir().join("notes.txt"),
+            cache.dir().join("Saves").join("slot1.sav"),
+            cache.dir().join("0000").join("readme.cacnk"),
+        ];
+        for file in &files {
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(file, vec![0; 512 * 1024]).unwrap();
+        }
+        files
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_chunks() {
+        let cache = setup("evict", 1);
+        for n in 0..3 {
+            cache.insert(&id(n), &vec![0; 400 * 1024]).unwrap();
+            let used = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(n + 1);
+            fs::File::options()
+                .write(true)
+                .open(cache.path_for(&id(n)))
+                .and_then(|file| file.set_modified(used))
+                .unwrap();
+        }
+        assert!(cache.get(&id(0)).is_some());
+
+        let evicted = cache.evict();
+        assert_eq!(evicted.files, 1);
+        assert!(cache.get(&id(1)).is_none());
+        assert!(cache.get(&id(0)).is_some());
+        assert!(cache.get(&id(2)).is_some());
+        fs::remove_dir_all(cache.dir()).unwrap();
+    }
+
+    #[test]
+    fn evicts_while_inserting() {
+        let cache = setup("insert", 1);
+        cache.insert(&id(0), &vec![0; 400 * 1024]).unwrap();
+        cache.insert(&id(1), &vec![0; 400 * 1024]).unwrap();
+        // As if most of a large install had gone in since the last check
+        cache.unchecked.store(EVICT_INTERVAL, Ordering::Relaxed);
+        cache.insert(&id(2), &vec![0; 400 * 1024]).unwrap();
+
+        assert!(cache.usage().bytes <= cache.limit());
+        assert_eq!(cache.evict().files, 1);
+        fs::remove_dir_all(cache.dir()).unwrap();
+    }
+
+    #[test]
+    fn leaves_files_that_are_not_chunks_alone() {
+        let cache = setup("foreign", 0);
+        let foreign = add_foreign_files(&cache);
+        cache.insert(&id(0), b"chunk").unwrap();
+        cache.insert(&id(1), b"chunk").unwrap();
+        let leftover = cache
+            .path_for(&id(2))
+            .with_extension(format!("{}.tmp", std::process::id()));
+        fs::write(&leftover, b"half a chunk").unwrap();
+
+        assert_eq!(cache.usage().files, 2);
+        let keep = HashSet::from([id(1)]);
+        let pruned = cache.prune(&keep).unwrap();
+        assert_eq!(pruned.files, 2);
+        assert!(!leftover.exists());
+        assert!(cache.get(&id(1)).is_some());
+
+        assert_eq!(cache.evict().files, 1);
+        assert_eq!(cache.usage().files, 0);
+        for file in &foreign {
+            assert!(file.is_file(), "{} was deleted", file.display());
+        }
+        fs::remove_dir_all(cache.dir()).unwrap();
+    }
+}
@@ -0,0 +1,51 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::PatcherError;
+
+/// How often blocking waits look at the token.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Flag shared between a running operation and whoever may want to stop it.
+/// The operation polls it at safe points and unwinds with
+/// `PatcherError::Cancelled`.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Fails with `Cancelled` once the token has been cancelled.
+    pub fn check(&self) -> Result<(), PatcherError> {
+        if self.is_cancelled() {
+            Err(PatcherError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Sleeps for `duration`, waking up early to fail once cancelled.
+    pub fn sleep(&self, duration: Duration) -> Result<(), PatcherError> {
+        let deadline = Instant::now() + duration;
+        loop {
+            self.check()?;
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Ok(());
+            }
+            thread::sleep(left.min(POLL_INTERVAL));
+        }
+    }
+}
@@ -0,0 +1,1181 @@
+// Reads casync archives the way `desync untar -i` does: a `.caidx` index
+// lists the chunks of a catar archive, and each chunk is fetched from a
+// chunk store as `<store>/<first 4 hex digits>/<chunk id>.cacnk`, zstd
+// compressed. Chunks are only fetched when bytes inside them are needed,
+// so the archive can be walked and compared with files on disk without
+// downloading the file contents. When every file is wanted, as for an
+// install, chunks are fetched ahead of the walk on several threads.
+// Like `desync untar --seed`, an install can take chunks from the files of
+// other installs whose index is known, instead of downloading them.
+
+use sha2::{Digest, Sha256, Sha512_256};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use crate::cache::ChunkCache;
+use crate::cancel::CancelToken;
+use crate::disk;
+use crate::error::PatcherError;
+use crate::http;
+
+// Object types and flags from casync's caformat.h
+const CA_FORMAT_INDEX: u64 = 0x96824d9c7b129ff9;
+const CA_FORMAT_TABLE: u64 = 0xe75b9e112f17417d;
+const CA_FORMAT_SHA512_256: u64 = 0x2000000000000000;
+const CA_FORMAT_ENTRY: u64 = 0x1396fabcea5bbb51;
+const CA_FORMAT_FILENAME: u64 = 0x6dbb6ebcb3161f0b;
+const CA_FORMAT_PAYLOAD: u64 = 0x8b9e1d93d6dcffc9;
+const CA_FORMAT_SYMLINK: u64 = 0x664a6fb6830e0d6c;
+const CA_FORMAT_GOODBYE: u64 = 0xdfd35c5e8327c403;
+
+const INDEX_HEADER_SIZE: usize = 48;
+const TABLE_HEADER_SIZE: usize = 16;
+const TABLE_ITEM_SIZE: usize = 40;
+/// Every catar object starts with its size and type.
+const OBJECT_HEADER_SIZE: u64 = 16;
+/// Objects other than payloads are small; anything bigger is corruption.
+const MAX_METADATA_SIZE: u64 = 1024 * 1024;
+
+const S_IFMT: u64 = 0o170000;
+const S_IFDIR: u64 = 0o040000;
+
+/// How many chunks each prefetching thread may run ahead of the reader.
+const CHUNKS_AHEAD_PER_THREAD: usize = 4;
+
+/// One chunk of the archive, covering bytes `start..end`.
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    pub id: [u8; 32],
+    pub start: u64,
+    pub end: u64,
+}
+
+impl Chunk {
+    pub fn id_hex(&self) -> String {
+        hex(&self.id)
+    }
+}
+
+/// A parsed `.caidx` file.
+#[derive(Debug)]
+pub struct Index {
+    chunks: Vec<Chunk>,
+    /// Chunk IDs are SHA512/256 rather than SHA256 digests.
+    sha512_256: bool,
+}
+
+impl Index {
+    pub fn load(path: &Path) -> Result<Self, PatcherError> {
+        let data = fs::read(path).map_err(PatcherError::io("Failed to read index", path))?;
+        Self::parse(&data).map_err(|message| PatcherError::Archive {
+            path: path.to_path_buf(),
+            message,
+        })
+    }
+
+    fn parse(data: &[u8]) -> Result<Self, String> {
+        let header = data
+            .get(..INDEX_HEADER_SIZE + TABLE_HEADER_SIZE)
+            .ok_or("index is truncated")?;
+        if u64_at(header, 8) != CA_FORMAT_INDEX {
+            return Err("not a casync index".to_string());
+        }
+        if u64_at(header, INDEX_HEADER_SIZE + 8) != CA_FORMAT_TABLE {
+            return Err("index has no chunk table".to_string());
+        }
+        let flags = u64_at(header, 16);
+
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        let mut pos = INDEX_HEADER_SIZE + TABLE_HEADER_SIZE;
+        loop {
+            let item = data
+                .get(pos..pos + TABLE_ITEM_SIZE)
+                .ok_or("index is truncated")?;
+            let end = u64_at(item, 0);
+            // The table tail starts with zeros where an offset would be
+            if end == 0 {
+                break;
+            }
+            if end <= start {
+                return Err(format!(
+                    "chunk {} does not end after it starts",
+                    chunks.len()
+                ));
+            }
+            let mut id = [0; 32];
+            id.copy_from_slice(&item[8..]);
+            chunks.push(Chunk { id, start, end });
+            start = end;
+            pos += TABLE_ITEM_SIZE;
+        }
+
+        Ok(Index {
+            chunks,
+            sha512_256: flags & CA_FORMAT_SHA512_256 != 0,
+        })
+    }
+
+    /// The IDs of the chunks, in hex, as chunk stores name them.
+    pub fn chunk_ids(&self) -> impl Iterator<Item = String> + '_ {
+        self.chunks.iter().map(Chunk::id_hex)
+    }
+
+    /// Size of the archive the index describes.
+    pub fn size(&self) -> u64 {
+        self.chunks.last().map_or(0, |chunk| chunk.end)
+    }
+
+    /// The position in `chunks` of the chunk holding byte `offset`.
+    fn chunk_at(&self, offset: u64) -> Option<usize> {
+        let i = self.chunks.partition_point(|chunk| chunk.end <= offset);
+        (i < self.chunks.len()).then_some(i)
+    }
+
+    /// The ID a chunk holding `data` has.
+    fn chunk_id(&self, data: &[u8]) -> [u8; 32] {
+        if self.sha512_256 {
+            Sha512_256::digest(data).into()
+        } else {
+            Sha256::digest(data).into()
+        }
+    }
+}
+
+/// A file, directory or link in the archive, with its path relative to the
+/// archive root.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub path: PathBuf,
+    pub kind: EntryKind,
+}
+
+#[derive(Debug, Clone)]
+pub enum EntryKind {
+    Directory,
+    /// A regular file whose contents are archive bytes `offset..offset + size`.
+    File {
+        offset: u64,
+        size: u64,
+    },
+    Symlink {
+        target: PathBuf,
+    },
+    /// Devices, FIFOs and sockets, which GYeet leaves alone.
+    Other,
+}
+
+/// Where a chunk can be read from the files of another install: it lies
+/// wholly inside one file of that install's archive.
+#[derive(Debug, Clone)]
+pub struct SeedChunk {
+    pub path: PathBuf,
+    pub offset: u64,
+}
+
+/// The chunk stores the chunks of an index are fetched from. Shared by the
+/// threads fetching chunks ahead of a walk.
+pub struct ChunkStore<'a> {
+    index: &'a Index,
+    urls: &'a [String],
+    cancel: &'a CancelToken,
+    /// Looked in before the stores, and keeps every chunk they serve.
+    cache: Option<&'a ChunkCache>,
+    /// Whether the cache shares a filesystem with the files being written.
+    cache_shares_disk: bool,
+    /// Chunks that local files may hold, looked in after the cache.
+    seeds: HashMap<[u8; 32], SeedChunk>,
+    /// The store that served the last chunk, tried first for the next one,
+    /// so a dead mirror costs its retries once rather than for every chunk.
+    preferred: AtomicUsize,
+    downloaded: AtomicU64,
+    /// Compressed bytes read from the chunk cache.
+    cached: AtomicU64,
+    /// Bytes read from seed files.
+    seeded: AtomicU64,
+    /// Failures that another store made up for, waiting to be reported.
+    warnings: Mutex<Warnings>,
+}
+
+#[derive(Default)]
+struct Warnings {
+    pending: Vec<String>,
+    /// Stores, and the chunk cache, already reported, so each problem is
+    /// mentioned only once.
+    stores: HashSet<String>,
+}
+
+impl<'a> ChunkStore<'a> {
+    pub fn new(
+        index: &'a Index,
+        urls: &'a [String],
+        cache: Option<&'a ChunkCache>,
+        cancel: &'a CancelToken,
+    ) -> Self {
+        ChunkStore {
+            index,
+            urls,
+            cancel,
+            cache,
+            cache_shares_disk: false,
+            seeds: HashMap::new(),
+            preferred: AtomicUsize::new(0),
+            downloaded: AtomicU64::new(0),
+            cached: AtomicU64::new(0),
+            seeded: AtomicU64::new(0),
+            warnings: Mutex::new(Warnings::default()),
+        }
+    }
+
+    /// Chunk store failures since the last call that did not stop the
+    /// operation because another store served the chunk.
+    pub fn take_warnings(&self) -> Vec<String> {
+        std::mem::take(&mut self.warnings.lock().unwrap().pending)
+    }
+
+    /// Compressed bytes fetched so far.
+    pub fn downloaded(&self) -> u64 {
+        self.downloaded.load(Ordering::Relaxed)
+    }
+
+    /// Compressed bytes found in the chunk cache so far.
+    pub fn cached(&self) -> u64 {
+        self.cached.load(Ordering::Relaxed)
+    }
+
+    /// Bytes taken from seed files so far.
+    pub fn seeded(&self) -> u64 {
+        self.seeded.load(Ordering::Relaxed)
+    }
+
+    /// Tells the store the files it serves chunks for are written under
+    /// `dir`. If the chunk cache is on the same filesystem, failing to keep
+    /// a chunk there fails the fetch instead of being reported once, as the
+    /// files would be the next to run out of space.
+    pub fn writing_to(&mut self, dir: &Path) {
+        self.cache_shares_disk = self
+            .cache
+            .is_some_and(|cache| disk::same_filesystem(cache.dir(), dir));
+    }
+
+    /// Lets chunks be read from local files, as found by `seed_chunks`.
+    /// Returns how many of this index's chunks the seed may provide.
+    pub fn add_seed(&mut self, chunks: Vec<([u8; 32], SeedChunk)>) -> usize {
+        let wanted: HashSet<[u8; 32]> = self.index.chunks.iter().map(|chunk| chunk.id).collect();
+        let before = self.seeds.len();
+        for (id, seed) in chunks {
+            if wanted.contains(&id) {
+                self.seeds.entry(id).or_insert(seed);
+            }
+        }
+        self.seeds.len() - before
+    }
+
+    /// Chunk `i`, decompressed and checked. Taken from the cache when it
+    /// is there, otherwise downloaded, trying each store in turn.
+    fn fetch(&self, i: usize) -> Result<Vec<u8>, PatcherError> {
+        self.cancel.check()?;
+        let chunk = &self.index.chunks[i];
+        let id = chunk.id_hex();
+        if let Some(cache) = self.cache {
+            if let Some(compressed) = cache.get(&id) {
+                match self.decode(chunk, &id, &compressed, &cache.dir().display().to_string()) {
+                    Ok(data) => {
+                        self.cached
+                            .fetch_add(compressed.len() as u64, Ordering::Relaxed);
+                        return Ok(data);
+                    }
+                    // Damaged; fetched again and replaced below
+                    Err(_) => cache.remove(&id),
+                }
+            }
+        }
+        if let Some(data) = self.read_seed(chunk) {
+            self.seeded.fetch_add(data.len() as u64, Ordering::Relaxed);
+            return Ok(data);
+        }
+
+        let mut urls = self.urls.to_vec();
+        urls.rotate_left(self.preferred.load(Ordering::Relaxed));
+        let mut served = None;
+        let mut failures = Vec::new();
+        let result = http::with_mirrors(&urls, "chunk store", |store| {
+            let result = self.fetch_from(store, chunk, &id);
+            match &result {
+                Ok(_) => served = Some(store.to_string()),
+                Err(e) => failures.push((store.to_string(), e.to_string())),
+            }
+            result
+        });
+        let result = result.and_then(|(data, compressed)| {
+            if let Some(cache) = self.cache {
+                if let Err(e) = cache.insert(&id, &compressed) {
+                    if self.cache_shares_disk {
+                        return Err(PatcherError::io(
+                            "Failed to write to the chunk cache",
+                            cache.dir(),
+                        )(e));
+                    }
+                    self.warn_once(
+                        &cache.dir().display().to_string(),
+                        format!(
+                            "Chunk cache {} is not being filled: {}",
+                            cache.dir().display(),
+                            e
+                        ),
+                    );
+                }
+            }
+            Ok(data)
+        });
+        if let Some(store) = served {
+            let position = self.urls.iter().position(|s| *s == store).unwrap_or(0);
+            self.preferred.store(position, Ordering::Relaxed);
+            for (store, message) in failures {
+                let warning = format!(
+                    "Chunk store {} failed, using the next one: {}",
+                    store, message
+                );
+                self.warn_once(&store, warning);
+            }
+        }
+        result
+    }
+
+    /// The chunk read from a seed file, if one should hold it and still
+    /// does.
+    fn read_seed(&self, chunk: &Chunk) -> Option<Vec<u8>> {
+        let seed = self.seeds.get(&chunk.id)?;
+        let mut file = fs::File::open(&seed.path).ok()?;
+        file.seek(SeekFrom::Start(seed.offset)).ok()?;
+        let mut data = vec![0; (chunk.end - chunk.start) as usize];
+        file.read_exact(&mut data).ok()?;
+        (self.index.chunk_id(&data) == chunk.id).then_some(data)
+    }
+
+    fn warn_once(&self, key: &str, warning: String) {
+        let mut warnings = self.warnings.lock().unwrap();
+        if warnings.stores.insert(key.to_string()) {
+            warnings.pending.push(warning);
+        }
+    }
+
+    /// Downloads a chunk from one store. Returns it decompressed and as
+    /// it was served.
+    fn fetch_from(
+        &self,
+        store: &str,
+        chunk: &Chunk,
+        id: &str,
+    ) -> Result<(Vec<u8>, Vec<u8>), PatcherError> {
+        let url = format!("{}/{}/{}.cacnk", store.trim_end_matches('/'), &id[..4], id);
+        let body = http::send(self.cancel, |client| client.get(&url))?
+            .and_then(|r| r.error_for_status())
+            .and_then(|r| r.bytes())
+            .map_err(PatcherError::network(&url))?;
+
+        let data = self.decode(chunk, id, &body, &url)?;
+        self.downloaded
+            .fetch_add(body.len() as u64, Ordering::Relaxed);
+        Ok((data, body.to_vec()))
+    }
+
+    /// Decompresses a chunk read from `source` and checks it is the one
+    /// the index lists.
+    fn decode(
+        &self,
+        chunk: &Chunk,
+        id: &str,
+        compressed: &[u8],
+        source: &str,
+    ) -> Result<Vec<u8>, PatcherError> {
+        let data = zstd::decode_all(compressed).map_err(|e| PatcherError::Network {
+            url: source.to_string(),
+            message: format!("chunk does not decompress: {}", e),
+        })?;
+        let actual = self.index.chunk_id(&data);
+        if actual != chunk.id || data.len() as u64 != chunk.end - chunk.start {
+            return Err(PatcherError::HashMismatch {
+                subject: source.to_string(),
+                expected: id.to_string(),
+                actual: hex(&actual),
+            });
+        }
+        Ok(data)
+    }
+}
+
+/// The catar archive an index describes, read from the chunk stores.
+pub struct Archive<'a> {
+    index: &'a Index,
+    index_path: &'a Path,
+    store: &'a ChunkStore<'a>,
+    /// The most recently fetched chunk, which the next read usually needs.
+    loaded: Option<(usize, Vec<u8>)>,
+    /// Chunks being fetched ahead of the reads, while prefetching.
+    ahead: Option<Prefetch>,
+}
+
+impl<'a> Archive<'a> {
+    pub fn new(store: &'a ChunkStore<'a>, index_path: &'a Path) -> Self {
+        Archive {
+            index: store.index,
+            index_path,
+            store,
+            loaded: None,
+            ahead: None,
+        }
+    }
+
+    /// Compressed bytes fetched from the chunk stores so far.
+    pub fn downloaded(&self) -> u64 {
+        self.store.downloaded()
+    }
+
+    /// The size of the archive in bytes.
+    pub fn size(&self) -> u64 {
+        self.index.size()
+    }
+
+    /// Walks the archive from the start, calling `visit` for each entry in
+    /// archive order; a directory always comes before what it contains.
+    /// `visit` may read the payload of the file it is given: the chunk at
+    /// its end is the one the walk needs next anyway.
+    pub fn walk<V>(&mut self, mut visit: V) -> Result<(), PatcherError>
+    where
+        V: FnMut(&mut Self, &Entry) -> Result<(), PatcherError>,
+    {
+        let total = self.index.size();
+        let mut pos = 0;
+        let mut dirs: Vec<PathBuf> = Vec::new();
+        let mut name: Option<String> = None;
+        // A non-directory entry waiting for its payload or link target
+        let mut pending: Option<PathBuf> = None;
+
+        while pos < total {
+            let mut header = [0; OBJECT_HEADER_SIZE as usize];
+            self.read_at(pos, &mut header)?;
+            let size = u64_at(&header, 0);
+            let kind = u64_at(&header, 8);
+            if size < OBJECT_HEADER_SIZE || size > total - pos {
+                return Err(self.corrupt(format!("bad object size at offset {}", pos)));
+            }
+            let body_offset = pos + OBJECT_HEADER_SIZE;
+            let body_size = size - OBJECT_HEADER_SIZE;
+
+            // An entry with neither payload nor target is a device, FIFO or
+            // socket; it ends where the next name or the directory ends
+            if matches!(kind, CA_FORMAT_FILENAME | CA_FORMAT_GOODBYE) {
+                if let Some(path) = pending.take() {
+                    let kind = EntryKind::Other;
+                    visit(self, &Entry { path, kind })?;
+                }
+            }
+
+            match kind {
+                CA_FORMAT_FILENAME => {
+                    let body = self.read_body(body_offset, body_size)?;
+                    let parsed = parse_name(&body);
+                    name =
+                        Some(parsed.ok_or_else(|| {
+                            self.corrupt(format!("bad file name at offset {}", pos))
+                        })?);
+                }
+                CA_FORMAT_ENTRY => {
+                    let body = self.read_body(body_offset, body_size)?;
+                    if body.len() < 16 {
+                        return Err(self.corrupt(format!("bad entry at offset {}", pos)));
+                    }
+                    let mode = u64_at(&body, 8);
+                    let path = match (dirs.last(), name.take()) {
+                        (Some(parent), Some(name)) => parent.join(name),
+                        (None, None) => PathBuf::new(),
+                        _ => return Err(self.corrupt(format!("unnamed entry at offset {}", pos))),
+                    };
+                    if mode & S_IFMT != S_IFDIR {
+                        pending = Some(path);
+                    } else {
+                        // The root itself is not reported
+                        if !dirs.is_empty() {
+                            let kind = EntryKind::Directory;
+                            visit(
+                                self,
+                                &Entry {
+                                    path: path.clone(),
+                                    kind,
+                                },
+                            )?;
+                        }
+                        dirs.push(path);
+                    }
+                }
+                CA_FORMAT_PAYLOAD => {
+                    let path = pending.take().ok_or_else(|| {
+                        self.corrupt(format!("payload without an entry at offset {}", pos))
+                    })?;
+                    let kind = EntryKind::File {
+                        offset: body_offset,
+                        size: body_size,
+                    };
+                    visit(self, &Entry { path, kind })?;
+                }
+                CA_FORMAT_SYMLINK => {
+                    let body = self.read_body(body_offset, body_size)?;
+                    let target = parse_string(&body).ok_or_else(|| {
+                        self.corrupt(format!("bad link target at offset {}", pos))
+                    })?;
+                    let path = pending.take().ok_or_else(|| {
+                        self.corrupt(format!("link without an entry at offset {}", pos))
+                    })?;
+                    let kind = EntryKind::Symlink {
+                        target: PathBuf::from(target),
+                    };
+                    visit(self, &Entry { path, kind })?;
+                }
+                CA_FORMAT_GOODBYE => {
+                    dirs.pop();
+                }
+                // Ownership, extended attributes, ACLs and other metadata
+                _ => {}
+            }
+            pos += size;
+        }
+        Ok(())
+    }
+
+    /// Like `walk`, but with `threads` threads fetching chunks ahead of the
+    /// walk. For walks that read every file; chunks the walk skips past
+    /// are dropped.
+    pub fn walk_prefetching<V>(&mut self, threads: usize, visit: V) -> Result<(), PatcherError>
+    where
+        V: FnMut(&mut Self, &Entry) -> Result<(), PatcherError>,
+    {
+        let all = (0..self.index.chunks.len()).collect();
+        self.prefetching(all, threads, |archive| archive.walk(visit))
+    }
+
+    /// Calls `extract` with `threads` threads fetching the chunks of the
+    /// payloads of `files` ahead of it, for extracting those files in
+    /// archive order. Other chunks are fetched when read, as usual.
+    pub fn prefetching_files<R, E>(
+        &mut self,
+        files: &[Entry],
+        threads: usize,
+        extract: E,
+    ) -> Result<R, PatcherError>
+    where
+        E: FnOnce(&mut Self) -> Result<R, PatcherError>,
+    {
+        let mut wanted: Vec<usize> = files
+            .iter()
+            .filter_map(|entry| match entry.kind {
+                EntryKind::File { offset, size } if size > 0 => Some((offset, size)),
+                _ => None,
+            })
+            .flat_map(|(offset, size)| {
+                let chunks = &self.index.chunks;
+                let first = chunks.partition_point(|chunk| chunk.end <= offset);
+                let last = chunks.partition_point(|chunk| chunk.start < offset + size);
+                first..last
+            })
+            .collect();
+        wanted.sort_unstable();
+        wanted.dedup();
+        self.prefetching(wanted, threads, extract)
+    }
+
+    /// Runs `run` while `threads` threads fetch the chunks in `order`, which
+    /// is sorted, a bounded window ahead of what `run` reads.
+    fn prefetching<R, Run>(
+        &mut self,
+        order: Vec<usize>,
+        threads: usize,
+        run: Run,
+    ) -> Result<R, PatcherError>
+    where
+        Run: FnOnce(&mut Self) -> Result<R, PatcherError>,
+    {
+        let threads = threads.max(1);
+        let store = self.store;
+        let order = Arc::new(order);
+        let window = Arc::new(Window::new(threads * CHUNKS_AHEAD_PER_THREAD));
+
+        thread::scope(|scope| {
+            let (sender, received) = mpsc::channel();
+            for _ in 0..threads {
+                let sender = sender.clone();
+                let order = Arc::clone(&order);
+                let window = Arc::clone(&window);
+                scope.spawn(move || {
+                    while let Some(next) = window.next(order.len()) {
+                        let i = order[next];
+                        if sender.send((i, store.fetch(i))).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            drop(sender);
+
+            self.ahead = Some(Prefetch {
+                order: Arc::clone(&order),
+                received,
+                ready: HashMap::new(),
+                window: Arc::clone(&window),
+                passed: 0,
+            });
+            let result = run(self);
+            // Let the threads go before the scope waits for them
+            self.ahead = None;
+            window.stop();
+            result
+        })
+    }
+
+    /// Every entry of the archive, in archive order. Only reads the
+    /// archive's metadata, not the file contents.
+    pub fn entries(&mut self) -> Result<Vec<Entry>, PatcherError> {
+        let mut entries = Vec::new();
+        self.walk(|_, entry| {
+            entries.push(entry.clone());
+            Ok(())
+        })?;
+        Ok(entries)
+    }
+
+    /// The chunks of the archive that lie wholly inside one of the files
+    /// among `entries`, with where that file's copy under `dir` would hold
+    /// them.
+    pub fn seed_chunks(&self, entries: &[Entry], dir: &Path) -> Vec<([u8; 32], SeedChunk)> {
+        let chunks = &self.index.chunks;
+        let mut found = Vec::new();
+        for entry in entries {
+            let EntryKind::File { offset, size } = entry.kind else {
+                continue;
+            };
+            let mut i = chunks.partition_point(|chunk| chunk.start < offset);
+            while i < chunks.len() && chunks[i].end <= offset + size {
+                let seed = SeedChunk {
+                    path: dir.join(&entry.path),
+                    offset: chunks[i].start - offset,
+                };
+                found.push((chunks[i].id, seed));
+                i += 1;
+            }
+        }
+        found
+    }
+
+    /// Whether `local` holds exactly the archive bytes `offset..offset + size`.
+    /// Chunks lying entirely inside that range are checked by hashing the
+    /// local bytes against the chunk ID, so they are never downloaded; only
+    /// the chunks at either end, shared with other objects, are fetched.
+    /// `progress` is called with the number of bytes checked so far.
+    pub fn file_matches<P>(
+        &mut self,
+        offset: u64,
+        size: u64,
+        local: &Path,
+        mut progress: P,
+    ) -> Result<bool, PatcherError>
+    where
+        P: FnMut(u64),
+    {
+        let mut file = fs::File::open(local).map_err(PatcherError::io("Failed to open", local))?;
+        let local_size = file
+            .metadata()
+            .map_err(PatcherError::io("Failed to read", local))?
+            .len();
+        if local_size != size {
+            return Ok(false);
+        }
+
+        let end = offset + size;
+        let mut pos = offset;
+        let mut buffer = Vec::new();
+        while pos < end {
+            self.store.cancel.check()?;
+            let i = self.chunk_index(pos)?;
+            let chunk = &self.index.chunks[i];
+            let (chunk_start, chunk_end, chunk_id) = (chunk.start, chunk.end, chunk.id);
+            let segment_end = chunk_end.min(end);
+            buffer.resize((segment_end - pos) as usize, 0);
+            file.read_exact(&mut buffer)
+                .map_err(PatcherError::io("Failed to read", local))?;
+
+            let same = if chunk_start >= offset && chunk_end <= end {
+                self.index.chunk_id(&buffer) == chunk_id
+            } else {
+                let from = (pos - chunk_start) as usize;
+                self.chunk(i)?[from..from + buffer.len()] == buffer[..]
+            };
+            if !same {
+                return Ok(false);
+            }
+            pos = segment_end;
+            progress(pos - offset);
+        }
+        Ok(true)
+    }
+
+    /// Writes the archive bytes `offset..offset + size` to a new file at
+    /// `dest`. `progress` is called with the number of bytes written so far.
+    pub fn extract_file<P>(
+        &mut self,
+        offset: u64,
+        size: u64,
+        dest: &Path,
+        mut progress: P,
+    ) -> Result<(), PatcherError>
+    where
+        P: FnMut(u64),
+    {
+        let mut file =
+            fs::File::create(dest).map_err(PatcherError::io("Failed to create", dest))?;
+        let end = offset + size;
+        let mut pos = offset;
+        while pos < end {
+            let i = self.chunk_index(pos)?;
+            let chunk_start = self.index.chunks[i].start;
+            let data = self.chunk(i)?;
+            let from = (pos - chunk_start) as usize;
+            let len = (data.len() - from).min((end - pos) as usize);
+            file.write_all(&data[from..from + len])
+                .map_err(PatcherError::io("Failed to write", dest))?;
+            pos += len as u64;
+            progress(pos - offset);
+        }
+        file.sync_all()
+            .map_err(PatcherError::io("Failed to write", dest))
+    }
+
+    /// Fills `buf` with the archive bytes starting at `offset`.
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), PatcherError> {
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let i = self.chunk_index(pos)?;
+            let chunk_start = self.index.chunks[i].start;
+            let data = self.chunk(i)?;
+            let from = (pos - chunk_start) as usize;
+            let len = (data.len() - from).min(buf.len() - done);
+            buf[done..done + len].copy_from_slice(&data[from..from + len]);
+            done += len;
+        }
+        Ok(())
+    }
+
+    fn read_body(&mut self, offset: u64, size: u64) -> Result<Vec<u8>, PatcherError> {
+        if size > MAX_METADATA_SIZE {
+            return Err(self.corrupt(format!("oversized object at offset {}", offset)));
+        }
+        let mut body = vec![0; size as usize];
+        self.read_at(offset, &mut body)?;
+        Ok(body)
+    }
+
+    fn chunk_index(&self, offset: u64) -> Result<usize, PatcherError> {
+        self.index
+            .chunk_at(offset)
+            .ok_or_else(|| self.corrupt("archive ends early".to_string()))
+    }
+
+    /// The contents of chunk `i`, fetched unless it is the one loaded last.
+    fn chunk(&mut self, i: usize) -> Result<&[u8], PatcherError> {
+        if self.loaded.as_ref().map(|(loaded, _)| *loaded) != Some(i) {
+            self.store.cancel.check()?;
+            let data = match self.ahead.as_mut().and_then(|ahead| ahead.take(i)) {
+                Some(result) => result?,
+                None => self.store.fetch(i)?,
+            };
+            self.loaded = Some((i, data));
+        }
+        Ok(&self.loaded.as_ref().expect("loaded above").1)
+    }
+
+    fn corrupt(&self, message: String) -> PatcherError {
+        PatcherError::Archive {
+            path: self.index_path.to_path_buf(),
+            message,
+        }
+    }
+}
+
+/// Chunks fetched by the prefetching threads, handed to the reader in the
+/// order it asks for them.
+struct Prefetch {
+    /// The chunks being fetched, sorted.
+    order: Arc<Vec<usize>>,
+    received: Receiver<(usize, Result<Vec<u8>, PatcherError>)>,
+    /// Chunks that arrived before the walk needed them.
+    ready: HashMap<usize, Result<Vec<u8>, PatcherError>>,
+    window: Arc<Window>,
+    /// The position in `order` of the last chunk the reader asked for.
+    passed: usize,
+}
+
+impl Prefetch {
+    /// Chunk `i`, once a thread has fetched it. None if it is not being
+    /// fetched, or was dropped already when the reader goes back to it.
+    fn take(&mut self, i: usize) -> Option<Result<Vec<u8>, PatcherError>> {
+        let position = self.order.binary_search(&i).ok()?;
+        if position < self.passed {
+            return None;
+        }
+        self.passed = position;
+        self.ready.retain(|&ready, _| ready >= i);
+        self.window.advance(position);
+        loop {
+            if let Some(result) = self.ready.remove(&i) {
+                return Some(result);
+            }
+            let (fetched, result) = self.received.recv().ok()?;
+            if fetched >= i {
+                self.ready.insert(fetched, result);
+            }
+        }
+    }
+}
+
+/// Hands out positions in the list of chunks to fetch, in order, keeping
+/// the threads at most `size` chunks ahead of the reader so memory use
+/// stays bounded.
+struct Window {
+    state: Mutex<WindowState>,
+    moved: Condvar,
+    size: usize,
+}
+
+struct WindowState {
+    next: usize,
+    reading: usize,
+    stopped: bool,
+}
+
+impl Window {
+    fn new(size: usize) -> Self {
+        Window {
+            state: Mutex::new(WindowState {
+                next: 0,
+                reading: 0,
+                stopped: false,
+            }),
+            moved: Condvar::new(),
+            size,
+        }
+    }
+
+    /// The position of the next chunk to fetch, waiting while the threads
+    /// are too far ahead. None once all `count` chunks are handed out or
+    /// the reader is done.
+    fn next(&self, count: usize) -> Option<usize> {
+        let mut state = self.state.lock().unwrap();
+        while !state.stopped && state.next < count && state.next >= state.reading + self.size {
+            state = self.moved.wait(state).unwrap();
+        }
+        if state.stopped || state.next >= count {
+            return None;
+        }
+        state.next += 1;
+        Some(state.next - 1)
+    }
+
+    /// Records that the reader has reached the chunk at `position`.
+    fn advance(&self, position: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.reading = state.reading.max(position);
+        self.moved.notify_all();
+    }
+
+    fn stop(&self) {
+        self.state.lock().unwrap().stopped = true;
+        self.moved.notify_all();
+    }
+}
+
+/// A file name from the archive, refused if it could lead outside the
+/// directory it is in.
+fn parse_name(body: &[u8]) -> Option<String> {
+    let name = parse_string(body)?;
+    let valid = !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\']);
+    valid.then_some(name)
+}
+
+/// A NUL terminated string.
+fn parse_string(body: &[u8]) -> Option<String> {
+    let (last, text) = body.split_last()?;
+    if *last != 0 || text.contains(&0) {
+        return None;
+    }
+    String::from_utf8(text.to_vec()).ok()
+}
+
+fn u64_at(data: &[u8], pos: usize) -> u64 {
+    u64::from_le_bytes(data[pos..pos + 8].try_into().expect("8 bytes"))
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    const S_IFREG: u64 = 0o100000;
+    /// Chunks are cut every this many archive bytes, so they straddle the
+    /// objects of the archive at arbitrary points.
+    const CHUNK_SIZE: usize = 700;
+
+    fn object(kind: u64, body: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&(OBJECT_HEADER_SIZE + body.len() as u64).to_le_bytes());
+        data.extend_from_slice(&kind.to_le_bytes());
+        data.extend_from_slice(body);
+        data
+    }
+
+    fn entry(mode: u64) -> Vec<u8> {
+        let mut body = vec![0; 40];
+        body[8..16].copy_from_slice(&mode.to_le_bytes());
+        object(CA_FORMAT_ENTRY, &body)
+    }
+
+    fn filename(name: &str) -> Vec<u8> {
+        let mut body = name.as_bytes().to_vec();
+        body.push(0);
+        object(CA_FORMAT_FILENAME, &body)
+    }
+
+    fn file(name: &str, contents: &[u8]) -> Vec<u8> {
+        let mut data = filename(name);
+        data.extend(entry(S_IFREG | 0o644));
+        data.extend(object(CA_FORMAT_PAYLOAD, contents));
+        data
+    }
+
+    fn goodbye() -> Vec<u8> {
+        object(CA_FORMAT_GOODBYE, &[0; 24])
+    }
+
+    /// Bytes that differ from chunk to chunk, so no two chunks share an ID.
+    fn contents(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 + i / 251) as u8).collect()
+    }
+
+    /// An archive holding `small.txt` and `sub/big.bin`.
+    fn archive_with(name: &str) -> Vec<u8> {
+        let mut data = entry(S_IFDIR | 0o755);
+        data.extend(file(name, b"hello"));
+        data.extend(filename("sub"));
+        data.extend(entry(S_IFDIR | 0o755));
+        data.extend(file("big.bin", &contents(3000)));
+        data.extend(goodbye());
+        data.extend(goodbye());
+        data
+    }
+
+    /// A chunk store over `archive`, with its chunks in a chunk cache so
+    /// nothing is downloaded.
+    struct Fixture {
+        dir: PathBuf,
+        index: Index,
+        cache: ChunkCache,
+        cancel: CancelToken,
+    }
+
+    impl Fixture {
+        fn new(name: &str, archive: &[u8]) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("gyeet-casync-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            let config = Config {
+                chunk_cache_dir: dir.join("chunks").display().to_string(),
+                ..Config::default()
+            };
+            let cache = ChunkCache::new(&config).unwrap();
+
+            let mut index = vec![0; INDEX_HEADER_SIZE];
+            index[..8].copy_from_slice(&(INDEX_HEADER_SIZE as u64).to_le_bytes());
+            index[8..16].copy_from_slice(&CA_FORMAT_INDEX.to_le_bytes());
+            index.extend_from_slice(&u64::MAX.to_le_bytes());
+            index.extend_from_slice(&CA_FORMAT_TABLE.to_le_bytes());
+            let mut end = 0;
+            for chunk in archive.chunks(CHUNK_SIZE) {
+                end += chunk.len() as u64;
+                let id: [u8; 32] = Sha256::digest(chunk).into();
+                index.extend_from_slice(&end.to_le_bytes());
+                index.extend_from_slice(&id);
+                cache
+                    .insert(&hex(&id), &zstd::encode_all(chunk, 0).unwrap())
+                    .unwrap();
+            }
+            index.extend_from_slice(&[0; TABLE_ITEM_SIZE]);
+
+            let index = Index::parse(&index).unwrap();
+            Fixture {
+                dir,
+                index,
+                cache,
+                cancel: CancelToken::new(),
+            }
+        }
+
+        fn store(&self) -> ChunkStore<'_> {
+            ChunkStore::new(&self.index, &[], Some(&self.cache), &self.cancel)
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn payload(entries: &[Entry], path: &str) -> (u64, u64) {
+        match entries
+            .iter()
+            .find(|e| e.path == Path::new(path))
+            .unwrap()
+            .kind
+        {
+            EntryKind::File { offset, size } => (offset, size),
+            ref kind => panic!("{} is {:?}", path, kind),
+        }
+    }
+
+    #[test]
+    fn walks_and_extracts_a_hand_built_archive() {
+        let fixture = Fixture::new("walk", &archive_with("small.txt"));
+        let store = fixture.store();
+        let index_path = fixture.dir.join("test.caidx");
+        let mut archive = Archive::new(&store, &index_path);
+
+        let entries = archive.entries().unwrap();
+        let paths: Vec<_> = entries.iter().map(|e| e.path.clone()).collect();
+        assert_eq!(
+            paths,
+            [
+                PathBuf::from("small.txt"),
+                PathBuf::from("sub"),
+                PathBuf::from("sub/big.bin")
+            ]
+        );
+        assert!(matches!(entries[1].kind, EntryKind::Directory));
+
+        let (offset, size) = payload(&entries, "sub/big.bin");
+        let dest = fixture.dir.join("big.bin");
+        archive.extract_file(offset, size, &dest, |_| {}).unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), contents(3000));
+        assert_eq!(store.downloaded(), 0);
+    }
+
+    #[test]
+    fn refuses_names_leading_outside_their_directory() {
+        for name in ["..", ".", "", "a/b", "/etc", "a\\b"] {
+            let mut body = name.as_bytes().to_vec();
+            body.push(0);
+            assert_eq!(parse_name(&body), None, "{:?}", name);
+        }
+        assert_eq!(parse_name(b"..a\0"), Some("..a".to_string()));
+
+        for name in ["..", "../escape", "/etc"] {
+            let fixture = Fixture::new("names", &archive_with(name));
+            let store = fixture.store();
+            let index_path = fixture.dir.join("test.caidx");
+            let error = Archive::new(&store, &index_path).entries().unwrap_err();
+            assert!(
+                error.to_string().contains("bad file name at offset 56"),
+                "{}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn reports_an_archive_that_ends_early() {
+        // Cut through the header of the last object
+        let mut data = archive_with("small.txt");
+        data.truncate(data.len() - goodbye().len() + 8);
+        let fixture = Fixture::new("truncated", &data);
+        let store = fixture.store();
+        let index_path = fixture.dir.join("test.caidx");
+        let error = Archive::new(&store, &index_path).entries().unwrap_err();
+        assert!(
+            matches!(&error, PatcherError::Archive { message, .. } if message == "archive ends early"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn seeds_only_chunks_wholly_inside_a_file() {
+        let data = archive_with("small.txt");
+        let fixture = Fixture::new("seeds", &data);
+        let store = fixture.store();
+        let index_path = fixture.dir.join("test.caidx");
+        let mut archive = Archive::new(&store, &index_path);
+        let entries = archive.entries().unwrap();
+
+        let (offset, size) = payload(&entries, "sub/big.bin");
+        let seeds = archive.seed_chunks(&entries, Path::new("/seed"));
+        // The payload starts and ends inside a chunk; only the ones
+        // between lie wholly inside it
+        assert_ne!(offset % CHUNK_SIZE as u64, 0);
+        assert_ne!((offset + size) % CHUNK_SIZE as u64, 0);
+        let first = offset.div_ceil(CHUNK_SIZE as u64);
+        let last = (offset + size) / CHUNK_SIZE as u64;
+        assert_eq!(seeds.len() as u64, last - first);
+
+        let file = contents(3000);
+        for (n, (id, seed)) in seeds.iter().enumerate() {
+            let chunk = &fixture.index.chunks[first as usize + n];
+            assert_eq!(*id, chunk.id);
+            assert_eq!(seed.path, Path::new("/seed/sub/big.bin"));
+            assert_eq!(seed.offset, chunk.start - offset);
+            let start = seed.offset as usize;
+            let bytes = &file[start..start + (chunk.end - chunk.start) as usize];
+            assert_eq!(fixture.index.chunk_id(bytes), chunk.id);
+        }
+    }
+
+    #[test]
+    fn compares_files_across_their_edge_chunks() {
+        let fixture = Fixture::new("matches", &archive_with("small.txt"));
+        let store = fixture.store();
+        let index_path = fixture.dir.join("test.caidx");
+        let mut archive = Archive::new(&store, &index_path);
+        let entries = archive.entries().unwrap();
+        let (offset, size) = payload(&entries, "sub/big.bin");
+        let local = fixture.dir.join("local.bin");
+
+        let mut matches = |data: &[u8]| {
+            fs::write(&local, data).unwrap();
+            archive.file_matches(offset, size, &local, |_| {}).unwrap()
+        };
+        let file = contents(3000);
+        assert!(matches(&file));
+        assert!(!matches(&file[..2999]));
+        // A changed byte in the first and last chunk, which the payload
+        // shares with other objects, and in one wholly inside it
+        for at in [0, 1500, 2999] {
+            let mut changed = file.clone();
+            changed[at] ^= 1;
+            assert!(!matches(&changed), "byte {}", at);
+        }
+
+        let (offset, size) = payload(&entries, "small.txt");
+        fs::write(&local, b"hello").unwrap();
+        assert!(archive.file_matches(offset, size, &local, |_| {}).unwrap());
+        fs::write(&local, b"hellO").unwrap();
+        assert!(!archive.file_matches(offset, size, &local, |_| {}).unwrap());
+    }
+}
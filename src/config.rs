@@ -2,12 +2,75 @@ use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::PathBuf;
 
+const DEFAULT_PATCH_MANIFEST_URL: &str = "https://votv.dev/patcher_assets/patch_manifest.json";
+const DEFAULT_INSTALL_CATALOG_URL: &str = "https://votv.dev/patcher_assets/index_manifest.json";
+const DEFAULT_STORE_URL: &str = "https://votv.dev/patcher_assets/256-1024-4096-store";
+const DEFAULT_DOWNLOAD_CACHE_LIMIT_MB: u64 = 4096;
+const DEFAULT_CHUNK_CACHE_LIMIT_MB: u64 = 8192;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub votv_exe_path: String,
     pub install_dir: String,
     pub steam_path: String,
     pub proton_version: String,
+    /// Version the game at `votv_exe_path` was last verified to be at.
+    #[serde(default)]
+    pub installed_version: String,
+    /// Minisign public key for manifest signatures. Empty uses the key
+    /// built into GYeet.
+    #[serde(default)]
+    pub manifest_public_key: String,
+    /// Accept manifests without a valid signature, for custom mirrors.
+    #[serde(default)]
+    pub allow_unsigned_manifests: bool,
+    /// Size limit of the download cache in MiB.
+    #[serde(default = "default_download_cache_limit_mb")]
+    pub download_cache_limit_mb: u64,
+    /// Where chunks fetched for installs are kept, so reinstalling or
+    /// installing another version reuses them. Empty disables the cache.
+    #[serde(default = "default_chunk_cache_dir")]
+    pub chunk_cache_dir: String,
+    /// Size limit of the chunk cache in MiB.
+    #[serde(default = "default_chunk_cache_limit_mb")]
+    pub chunk_cache_limit_mb: u64,
+
+    // Endpoints, each an ordered list of mirrors tried in turn
+    #[serde(default = "default_patch_manifest_urls")]
+    pub patch_manifest_urls: Vec<String>,
+    #[serde(default = "default_install_catalog_urls")]
+    pub install_catalog_urls: Vec<String>,
+    #[serde(default = "default_store_urls")]
+    pub store_urls: Vec<String>,
+}
+
+fn default_download_cache_limit_mb() -> u64 {
+    DEFAULT_DOWNLOAD_CACHE_LIMIT_MB
+}
+
+fn default_chunk_cache_limit_mb() -> u64 {
+    DEFAULT_CHUNK_CACHE_LIMIT_MB
+}
+
+fn default_chunk_cache_dir() -> String {
+    dirs::cache_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("gyeet")
+        .join("chunks")
+        .to_string_lossy()
+        .to_string()
+}
+
+fn default_patch_manifest_urls() -> Vec<String> {
+    vec![DEFAULT_PATCH_MANIFEST_URL.to_string()]
+}
+
+fn default_install_catalog_urls() -> Vec<String> {
+    vec![DEFAULT_INSTALL_CATALOG_URL.to_string()]
+}
+
+fn default_store_urls() -> Vec<String> {
+    vec![DEFAULT_STORE_URL.to_string()]
 }
 
 impl Default for Config {
@@ -19,6 +82,15 @@ impl Default for Config {
             install_dir: home.join("Downloads/VOTV").to_string_lossy().to_string(),
             steam_path: home.join(".steam/steam").to_string_lossy().to_string(),
             proton_version: "Auto-detect".to_string(),
+            installed_version: String::new(),
+            manifest_public_key: String::new(),
+            allow_unsigned_manifests: false,
+            download_cache_limit_mb: default_download_cache_limit_mb(),
+            chunk_cache_dir: default_chunk_cache_dir(),
+            chunk_cache_limit_mb: default_chunk_cache_limit_mb(),
+            patch_manifest_urls: default_patch_manifest_urls(),
+            install_catalog_urls: default_install_catalog_urls(),
+            store_urls: default_store_urls(),
         }
     }
 }
@@ -51,4 +123,3 @@ impl Config {
         Ok(())
     }
 }
-
@@ -0,0 +1,100 @@
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use crate::error::PatcherError;
+
+/// Adds up what an operation is about to write on each filesystem, so it
+/// can refuse to start instead of running out of space halfway.
+#[derive(Debug, Default)]
+pub struct SpaceCheck {
+    needs: Vec<(PathBuf, u64)>,
+}
+
+impl SpaceCheck {
+    pub fn new() -> Self {
+        SpaceCheck::default()
+    }
+
+    /// Records that `bytes` will be written under `path`, which does not
+    /// have to exist yet.
+    pub fn need(&mut self, path: &Path, bytes: u64) {
+        self.needs.push((path.to_path_buf(), bytes));
+    }
+
+    /// Checks every filesystem has room for what was recorded on it.
+    pub fn check(&self) -> Result<(), PatcherError> {
+        // Paths on the same filesystem share its free space
+        let mut filesystems: Vec<(u64, PathBuf, u64)> = Vec::new();
+        for (path, bytes) in &self.needs {
+            let existing = existing_ancestor(path);
+            let device = fs::metadata(&existing)
+                .map_err(PatcherError::io("Failed to read", &existing))?
+                .dev();
+            match filesystems.iter_mut().find(|(d, _, _)| *d == device) {
+                Some((_, _, needed)) => *needed += bytes,
+                None => filesystems.push((device, existing, *bytes)),
+            }
+        }
+
+        for (_, path, needed) in filesystems {
+            let available = free_space(&path)
+                .map_err(PatcherError::io("Failed to check free space on", &path))?;
+            if needed > available {
+                return Err(PatcherError::NotEnoughSpace {
+                    path,
+                    needed,
+                    available,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Bytes that unprivileged users can still write on the filesystem holding
+/// `path`.
+pub fn free_space(path: &Path) -> io::Result<u64> {
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    // SAFETY: statvfs is plain data, so all zeroes is a valid value.
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: the path is NUL terminated and stat outlives the call.
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// Whether `a` and `b`, which do not have to exist yet, are on the same
+/// filesystem.
+pub fn same_filesystem(a: &Path, b: &Path) -> bool {
+    let device = |path: &Path| fs::metadata(existing_ancestor(path)).map(|m| m.dev());
+    matches!((device(a), device(b)), (Ok(a), Ok(b)) if a == b)
+}
+
+/// `path` itself or the closest of its parents that exists.
+fn existing_ancestor(path: &Path) -> PathBuf {
+    path.ancestors()
+        .find(|ancestor| !ancestor.as_os_str().is_empty() && ancestor.exists())
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["bytes", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
@@ -0,0 +1,322 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::disk;
+
+/// Everything that can go wrong while checking, patching, installing or
+/// restoring the game.
+#[derive(Debug, Clone)]
+pub enum PatcherError {
+    /// The selected file is missing or is not VotV.exe.
+    InvalidGamePath {
+        path: PathBuf,
+        reason: &'static str,
+    },
+    PakNotFound {
+        path: PathBuf,
+    },
+    /// The main pak's hash is not in the manifest: the pak itself is modded,
+    /// corrupted or newer than the manifest. Mod paks next to it do not
+    /// change its hash.
+    UnknownVersion {
+        hash: String,
+    },
+    NoRoute {
+        from: String,
+        to: String,
+    },
+    /// The install catalog has no entry, and so no index, for this version.
+    NotInCatalog {
+        version: String,
+    },
+    /// A local patch archive that does not appear in the manifest.
+    UnknownPatch {
+        path: PathBuf,
+        hash: String,
+    },
+    /// A local patch archive made for a different version than installed.
+    WrongBaseVersion {
+        patch_from: String,
+        patch_to: String,
+        installed: String,
+    },
+    NoCachedManifest,
+    Network {
+        url: String,
+        message: String,
+    },
+    HttpStatus {
+        url: String,
+        status: u16,
+    },
+    /// Every mirror of an endpoint failed; holds each mirror's error.
+    AllMirrorsFailed {
+        what: String,
+        errors: Vec<(String, PatcherError)>,
+    },
+    NoMirrors {
+        what: String,
+    },
+    ManifestParse {
+        what: String,
+        message: String,
+    },
+    Signature {
+        what: String,
+        message: String,
+    },
+    HashMismatch {
+        subject: String,
+        expected: String,
+        actual: String,
+    },
+    /// The pak does not hash to the version a patch should have produced.
+    VersionMismatch {
+        expected: String,
+        hash: String,
+        found: Option<String>,
+    },
+    Archive {
+        path: PathBuf,
+        message: String,
+    },
+    Diff {
+        path: PathBuf,
+        message: String,
+    },
+    NoBackup {
+        game_dir: PathBuf,
+    },
+    /// The user stopped the operation.
+    Cancelled,
+    /// The operation would fill up the filesystem holding `path`.
+    NotEnoughSpace {
+        path: PathBuf,
+        needed: u64,
+        available: u64,
+    },
+    /// The update failed and the previous version was put back.
+    RolledBack {
+        error: Box<PatcherError>,
+        version: String,
+    },
+    /// The update failed and putting the previous version back failed too.
+    RollbackFailed {
+        error: Box<PatcherError>,
+        restore_error: Box<PatcherError>,
+    },
+    Io {
+        action: &'static str,
+        path: PathBuf,
+        source: Arc<io::Error>,
+    },
+}
+
+impl PatcherError {
+    /// Adapter for `map_err` on filesystem calls.
+    pub fn io(action: &'static str, path: &Path) -> impl FnOnce(io::Error) -> Self {
+        let path = path.to_path_buf();
+        move |source| PatcherError::Io {
+            action,
+            path,
+            source: Arc::new(source),
+        }
+    }
+
+    /// Adapter for `map_err` on HTTP requests.
+    pub fn network(url: &str) -> impl FnOnce(reqwest::Error) -> Self {
+        let url = url.to_string();
+        move |e| match e.status() {
+            Some(status) => PatcherError::HttpStatus {
+                url,
+                status: status.as_u16(),
+            },
+            None => PatcherError::Network {
+                url,
+                message: e.to_string(),
+            },
+        }
+    }
+
+    /// Whether the operation stopped because the user cancelled it.
+    pub fn is_cancelled(&self) -> bool {
+        match self {
+            PatcherError::Cancelled => true,
+            PatcherError::RolledBack { error, .. } | PatcherError::RollbackFailed { error, .. } => {
+                error.is_cancelled()
+            }
+            _ => false,
+        }
+    }
+
+    /// What the user can do about it, if there is something specific.
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            PatcherError::UnknownVersion { .. } => Some(
+                "Extra paks such as those in ~mods are not the cause. Restore the original pak with \"Verify & Repair\" or reinstall from the Install tab.",
+            ),
+            PatcherError::NoRoute { .. } | PatcherError::WrongBaseVersion { .. } => {
+                Some("Install the version you want from the Install tab instead.")
+            }
+            PatcherError::NoCachedManifest => {
+                Some("Choose a manifest file, or check for updates online once.")
+            }
+            PatcherError::Network { .. }
+            | PatcherError::HttpStatus { .. }
+            | PatcherError::AllMirrorsFailed { .. } => {
+                Some("Check your internet connection, or add a mirror in config.json.")
+            }
+            PatcherError::Signature { .. } => Some(
+                "Set the manifest signing key in Settings. Only allow unsigned manifests for a mirror you trust.",
+            ),
+            PatcherError::HashMismatch { .. } => Some("The download was corrupted. Try again."),
+            PatcherError::NotEnoughSpace { .. } => Some(
+                "Old downloads can be removed with \"Clear Cache\" in Settings.",
+            ),
+            PatcherError::RolledBack { error, .. } => error.hint(),
+            PatcherError::RollbackFailed { .. } => Some(
+                "Your game files may be damaged. Try \"Restore Previous Version\" or \"Verify & Repair\".",
+            ),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for PatcherError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatcherError::InvalidGamePath { path, reason } => {
+                write!(f, "{}: {}", reason, path.display())
+            }
+            PatcherError::PakNotFound { path } => {
+                write!(f, "Pak file not found: {}", path.display())
+            }
+            PatcherError::UnknownVersion { hash } => write!(
+                f,
+                "Game version not recognized: the main pak itself is modified or damaged (SHA256 {})",
+                hash
+            ),
+            PatcherError::NoRoute { from, to } => {
+                write!(f, "No patch route from version {} to {}", from, to)
+            }
+            PatcherError::NotInCatalog { version } => {
+                write!(f, "Version {} is not in the install catalog", version)
+            }
+            PatcherError::UnknownPatch { path, hash } => write!(
+                f,
+                "{} is not a patch listed in the manifest (SHA256 {})",
+                path.display(),
+                hash
+            ),
+            PatcherError::WrongBaseVersion {
+                patch_from,
+                patch_to,
+                installed,
+            } => write!(
+                f,
+                "This patch upgrades {} -> {}, but the game is at version {}",
+                patch_from, patch_to, installed
+            ),
+            PatcherError::NoCachedManifest => write!(f, "No downloaded patch manifest yet"),
+            PatcherError::Network { url, message } => {
+                write!(f, "Failed to reach {}: {}", url, message)
+            }
+            PatcherError::HttpStatus { url, status } => {
+                write!(f, "Server returned HTTP {} for {}", status, url)
+            }
+            PatcherError::AllMirrorsFailed { what, errors } => {
+                write!(f, "All {} {} mirrors failed:", errors.len(), what)?;
+                for (url, error) in errors {
+                    let message = error.to_string();
+                    if message.contains(url.as_str()) {
+                        write!(f, "\n  {}", message)?;
+                    } else {
+                        write!(f, "\n  {}: {}", url, message)?;
+                    }
+                }
+                Ok(())
+            }
+            PatcherError::NoMirrors { what } => write!(f, "No {} URLs configured", what),
+            PatcherError::ManifestParse { what, message } => {
+                write!(f, "Failed to parse {}: {}", what, message)
+            }
+            PatcherError::Signature { what, message } => {
+                write!(f, "Refusing unverified {}: {}", what, message)
+            }
+            PatcherError::HashMismatch {
+                subject,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "SHA256 mismatch for {}! Expected {}, got {}",
+                subject, expected, actual
+            ),
+            PatcherError::VersionMismatch {
+                expected,
+                hash,
+                found,
+            } => write!(
+                f,
+                "Expected version {} after patching, but pak hash {} maps to {}",
+                expected,
+                hash,
+                found.as_deref().unwrap_or("no known version")
+            ),
+            PatcherError::Archive { path, message } => {
+                write!(f, "Failed to extract {}: {}", path.display(), message)
+            }
+            PatcherError::Diff { path, message } => {
+                write!(f, "Failed to apply {}: {}", path.display(), message)
+            }
+            PatcherError::NoBackup { game_dir } => write!(
+                f,
+                "No backup found for {}. A backup is made before each update.",
+                game_dir.display()
+            ),
+            PatcherError::Cancelled => write!(f, "Stopped by the user"),
+            PatcherError::NotEnoughSpace {
+                path,
+                needed,
+                available,
+            } => write!(
+                f,
+                "Not enough disk space on {}: {} needed, {} free. Free up at least {}.",
+                path.display(),
+                disk::format_size(*needed),
+                disk::format_size(*available),
+                disk::format_size(needed - available)
+            ),
+            PatcherError::RolledBack { error, version } => {
+                write!(f, "{} (version {} was restored)", error, version)
+            }
+            PatcherError::RollbackFailed {
+                error,
+                restore_error,
+            } => write!(
+                f,
+                "{}; restoring the previous version also failed: {}",
+                error, restore_error
+            ),
+            PatcherError::Io {
+                action,
+                path,
+                source,
+            } => write!(f, "{} {}: {}", action, path.display(), source),
+        }
+    }
+}
+
+impl std::error::Error for PatcherError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PatcherError::Io { source, .. } => Some(source.as_ref()),
+            PatcherError::RolledBack { error, .. } | PatcherError::RollbackFailed { error, .. } => {
+                Some(error.as_ref())
+            }
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// SHA256 hashes of large files, remembered between runs so an unchanged
+/// pak does not have to be read again. A hash is only reused while the
+/// file's path, size, modification time and inode all still match.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct HashCache {
+    files: HashMap<PathBuf, Record>,
+}
+
+/// What identifies one version of a file on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileStamp {
+    size: u64,
+    modified_secs: u64,
+    modified_nanos: u32,
+    inode: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Record {
+    stamp: FileStamp,
+    sha256: String,
+}
+
+impl FileStamp {
+    pub fn read(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+
+        #[cfg(unix)]
+        let inode = std::os::unix::fs::MetadataExt::ino(&metadata);
+        #[cfg(not(unix))]
+        let inode = 0;
+
+        Some(FileStamp {
+            size: metadata.len(),
+            modified_secs: modified.as_secs(),
+            modified_nanos: modified.subsec_nanos(),
+            inode,
+        })
+    }
+}
+
+impl HashCache {
+    fn state_path() -> PathBuf {
+        dirs::state_dir()
+            .or_else(dirs::data_local_dir)
+            .unwrap_or_else(std::env::temp_dir)
+            .join("gyeet")
+            .join("hashes.json")
+    }
+
+    pub fn load() -> Self {
+        fs::read(Self::state_path())
+            .ok()
+            .and_then(|content| serde_json::from_slice(&content).ok())
+            .unwrap_or_default()
+    }
+
+    /// The remembered hash of `path`, if the file has not changed since.
+    pub fn get(&self, path: &Path) -> Option<&str> {
+        let record = self.files.get(path)?;
+        (FileStamp::read(path)? == record.stamp).then_some(record.sha256.as_str())
+    }
+
+    /// Remembers `sha256` for `path` as it was when `stamp` was read, and
+    /// saves the cache.
+    pub fn insert(&mut self, path: &Path, stamp: FileStamp, sha256: &str) -> io::Result<()> {
+        // Drop entries for files that are gone, so the state stays small
+        self.files.retain(|path, _| path.exists());
+        self.files.insert(
+            path.to_path_buf(),
+            Record {
+                stamp,
+                sha256: sha256.to_string(),
+            },
+        );
+
+        let state_path = Self::state_path();
+        if let Some(parent) = state_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let content = serde_json::to_vec(self).expect("hash cache serializes");
+        fs::write(&state_path, content)
+    }
+}
@@ -0,0 +1,682 @@
+use std::fs;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::cancel::CancelToken;
+use crate::error::PatcherError;
+use crate::progress::ProgressEvent;
+
+// Native applier for HDiffPatch "compressed diff" files (HDIFF13), the format
+// produced by `hdiffz` by default and consumed by the bundled `hpatchz`.
+//
+// Layout:
+//   "HDIFF13&" compressType '\0'
+//   newDataSize oldDataSize coverCount
+//   cover_buf_size      compress_cover_buf_size
+//   rle_ctrlBuf_size    compress_rle_ctrlBuf_size
+//   rle_codeBuf_size    compress_rle_codeBuf_size
+//   newDataDiff_size    compress_newDataDiff_size
+//   [covers] [rle ctrl] [rle code] [new data diff]
+//
+// A section is stored compressed when its compress_* size is non-zero.
+
+const HDIFF13_MAGIC: &[u8] = b"HDIFF13&";
+const HDIFF_PREFIX: &[u8] = b"HDIFF";
+const MAX_HEADER_SIZE: usize = 4096;
+const COPY_BUFFER_SIZE: usize = 1024 * 1024;
+const PROGRESS_STEP: u64 = 8 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy)]
+struct Section {
+    size: u64,
+    compressed_size: u64,
+}
+
+impl Section {
+    fn stored_size(&self) -> u64 {
+        if self.compressed_size > 0 {
+            self.compressed_size
+        } else {
+            self.size
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct HDiffHeader {
+    pub compress_type: String,
+    pub new_size: u64,
+    pub old_size: u64,
+    cover_count: u64,
+    covers: Section,
+    rle_ctrl: Section,
+    rle_code: Section,
+    new_data_diff: Section,
+    data_offset: u64,
+}
+
+impl HDiffHeader {
+    pub fn read(diff_path: &Path) -> Result<Self, PatcherError> {
+        let mut file =
+            fs::File::open(diff_path).map_err(PatcherError::io("Failed to open", diff_path))?;
+        let mut head = Vec::with_capacity(MAX_HEADER_SIZE);
+        (&mut file)
+            .take(MAX_HEADER_SIZE as u64)
+            .read_to_end(&mut head)
+            .map_err(PatcherError::io("Failed to read", diff_path))?;
+
+        if !head.starts_with(HDIFF13_MAGIC) {
+            if head.starts_with(HDIFF_PREFIX) {
+                let kind: String = head
+                    .iter()
+                    .take_while(|b| b.is_ascii_alphanumeric())
+                    .map(|&b| b as char)
+                    .collect();
+                return Err(invalid(
+                    diff_path,
+                    format!("Unsupported HDiffPatch format: {}", kind),
+                ));
+            }
+            return Err(invalid(diff_path, "Not an HDiffPatch file"));
+        }
+
+        let mut cursor = &head[HDIFF13_MAGIC.len()..];
+        let type_end = cursor
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(|| invalid(diff_path, "Corrupt diff header: unterminated compress type"))?;
+        let compress_type = String::from_utf8_lossy(&cursor[..type_end]).to_string();
+        cursor = &cursor[type_end + 1..];
+
+        let mut next = || -> Result<u64, PatcherError> {
+            read_packed_uint(&mut cursor, 0)
+                .map(|(value, _)| value)
+                .map_err(|_| invalid(diff_path, "Corrupt diff header: truncated"))
+        };
+
+        let new_size = next()?;
+        let old_size = next()?;
+        let cover_count = next()?;
+        let mut section = || -> Result<Section, PatcherError> {
+            Ok(Section {
+                size: next()?,
+                compressed_size: next()?,
+            })
+        };
+        let covers = section()?;
+        let rle_ctrl = section()?;
+        let rle_code = section()?;
+        let new_data_diff = section()?;
+
+        let data_offset = (head.len() - cursor.len()) as u64;
+        let diff_len = file
+            .metadata()
+            .map_err(PatcherError::io("Failed to read", diff_path))?
+            .len();
+        let payload_len = covers.stored_size()
+            + rle_ctrl.stored_size()
+            + rle_code.stored_size()
+            + new_data_diff.stored_size();
+        if data_offset + payload_len > diff_len {
+            return Err(invalid(
+                diff_path,
+                "Corrupt diff: file is shorter than its header declares",
+            ));
+        }
+
+        Ok(HDiffHeader {
+            compress_type,
+            new_size,
+            old_size,
+            cover_count,
+            covers,
+            rle_ctrl,
+            rle_code,
+            new_data_diff,
+            data_offset,
+        })
+    }
+
+    fn section_reader(
+        &self,
+        diff_path: &Path,
+        index: usize,
+    ) -> Result<Box<dyn Read>, PatcherError> {
+        let sections = [
+            self.covers,
+            self.rle_ctrl,
+            self.rle_code,
+            self.new_data_diff,
+        ];
+        let offset = self.data_offset
+            + sections[..index]
+                .iter()
+                .map(Section::stored_size)
+                .sum::<u64>();
+        let section = sections[index];
+
+        let mut file =
+            fs::File::open(diff_path).map_err(PatcherError::io("Failed to open", diff_path))?;
+        file.seek(SeekFrom::Start(offset))
+            .map_err(PatcherError::io("Failed to read", diff_path))?;
+        let raw = BufReader::new(file).take(section.stored_size());
+
+        if section.compressed_size == 0 {
+            return Ok(Box::new(raw));
+        }
+
+        let decoder: Box<dyn Read> = match self.compress_type.as_str() {
+            "zstd" => {
+                Box::new(zstd::stream::read::Decoder::with_buffer(raw).map_err(|e| {
+                    invalid(diff_path, format!("Failed to init zstd decoder: {}", e))
+                })?)
+            }
+            "lzma" => {
+                // [propsSize][props: lc/lp/pb byte + dict size LE][raw lzma]
+                let mut raw = raw;
+                let props_size = read_u8(&mut raw)
+                    .map_err(|e| invalid(diff_path, format!("Failed to read lzma props: {}", e)))?
+                    as usize;
+                if props_size != 5 {
+                    return Err(invalid(
+                        diff_path,
+                        format!("Unsupported lzma props size: {}", props_size),
+                    ));
+                }
+                let mut props = [0u8; 5];
+                raw.read_exact(&mut props)
+                    .map_err(|e| invalid(diff_path, format!("Failed to read lzma props: {}", e)))?;
+                let dict_size = u32::from_le_bytes([props[1], props[2], props[3], props[4]]);
+                Box::new(
+                    lzma_rust::LZMAReader::new_with_props(
+                        raw,
+                        section.size,
+                        props[0],
+                        dict_size,
+                        None,
+                    )
+                    .map_err(|e| {
+                        invalid(diff_path, format!("Failed to init lzma decoder: {}", e))
+                    })?,
+                )
+            }
+            "lzma2" => {
+                // [dict size property byte][lzma2 chunks]
+                let mut raw = raw;
+                let prop = read_u8(&mut raw).map_err(|e| {
+                    invalid(diff_path, format!("Failed to read lzma2 props: {}", e))
+                })?;
+                let dict_size = lzma2_dict_size(prop).map_err(|e| invalid(diff_path, e))?;
+                Box::new(lzma_rust::LZMA2Reader::new(raw, dict_size, None))
+            }
+            other => {
+                return Err(invalid(
+                    diff_path,
+                    format!(
+                        "Unsupported diff compression '{}' (supported: zstd, lzma, lzma2)",
+                        other
+                    ),
+                ))
+            }
+        };
+
+        Ok(Box::new(decoder.take(section.size)))
+    }
+}
+
+/// Returns true if the file starts with an HDiffPatch signature.
+pub fn is_hdiff_file(path: &Path) -> bool {
+    let mut magic = [0u8; 5];
+    fs::File::open(path)
+        .and_then(|mut f| f.read_exact(&mut magic))
+        .map(|_| magic == HDIFF_PREFIX)
+        .unwrap_or(false)
+}
+
+/// Applies `diff_path` to `old_path`, writing the result to `out_path`.
+/// Stops early with `Cancelled` once `cancel` is cancelled.
+pub fn apply<F>(
+    old_path: &Path,
+    diff_path: &Path,
+    out_path: &Path,
+    cancel: &CancelToken,
+    output_callback: &mut F,
+) -> Result<(), PatcherError>
+where
+    F: FnMut(ProgressEvent),
+{
+    let header = HDiffHeader::read(diff_path)?;
+
+    let old_len = fs::metadata(old_path)
+        .map_err(PatcherError::io("Failed to read", old_path))?
+        .len();
+    if old_len != header.old_size {
+        return Err(invalid(
+            diff_path,
+            format!(
+                "Diff expects a {} byte source but {} is {} bytes",
+                header.old_size,
+                old_path.display(),
+                old_len
+            ),
+        ));
+    }
+
+    output_callback(ProgressEvent::info(format!(
+        "Diff: {} -> {} bytes, {} covers, compression: {}",
+        header.old_size,
+        header.new_size,
+        header.cover_count,
+        if header.compress_type.is_empty() {
+            "none"
+        } else {
+            header.compress_type.as_str()
+        }
+    )));
+
+    let mut covers = header.section_reader(diff_path, 0)?;
+    let mut rle = RleDecoder::new(
+        header.section_reader(diff_path, 1)?,
+        header.section_reader(diff_path, 2)?,
+    );
+    let mut new_data_diff = header.section_reader(diff_path, 3)?;
+
+    let mut old = fs::File::open(old_path).map_err(PatcherError::io("Failed to open", old_path))?;
+    let out_file =
+        fs::File::create(out_path).map_err(PatcherError::io("Failed to create", out_path))?;
+    let mut out = BufWriter::with_capacity(COPY_BUFFER_SIZE, out_file);
+
+    let mut progress = Progress::new(header.new_size, cancel);
+    let mut buffer = vec![0u8; COPY_BUFFER_SIZE];
+    let mut last_old_end = 0u64;
+    let mut last_new_end = 0u64;
+    let corrupt = |e: std::io::Error| invalid(diff_path, format!("Corrupt diff data: {}", e));
+
+    for _ in 0..header.cover_count {
+        let (old_delta, backwards) = read_packed_uint(&mut covers, 1).map_err(corrupt)?;
+        let (new_delta, _) = read_packed_uint(&mut covers, 0).map_err(corrupt)?;
+        let (length, _) = read_packed_uint(&mut covers, 0).map_err(corrupt)?;
+
+        let old_pos = if backwards != 0 {
+            last_old_end.checked_sub(old_delta)
+        } else {
+            last_old_end.checked_add(old_delta)
+        }
+        .ok_or_else(|| invalid(diff_path, "Corrupt diff: cover position out of range"))?;
+        let new_pos = last_new_end
+            .checked_add(new_delta)
+            .ok_or_else(|| invalid(diff_path, "Corrupt diff: cover position out of range"))?;
+        if old_pos.saturating_add(length) > header.old_size
+            || new_pos.saturating_add(length) > header.new_size
+        {
+            return Err(invalid(
+                diff_path,
+                "Corrupt diff: cover exceeds file bounds",
+            ));
+        }
+
+        // Bytes between covers come verbatim from the new data diff.
+        copy_new_data(
+            diff_path,
+            &mut new_data_diff,
+            &mut rle,
+            &mut out,
+            new_pos - last_new_end,
+            &mut buffer,
+            &mut progress,
+            output_callback,
+        )?;
+
+        // Covered bytes are old bytes plus the RLE-coded delta.
+        old.seek(SeekFrom::Start(old_pos))
+            .map_err(PatcherError::io("Failed to read", old_path))?;
+        let mut remaining = length;
+        while remaining > 0 {
+            let step = remaining.min(buffer.len() as u64) as usize;
+            old.read_exact(&mut buffer[..step])
+                .map_err(PatcherError::io("Failed to read", old_path))?;
+            rle.add(&mut buffer[..step]).map_err(corrupt)?;
+            out.write_all(&buffer[..step])
+                .map_err(PatcherError::io("Failed to write", out_path))?;
+            remaining -= step as u64;
+            progress.advance(step as u64, output_callback)?;
+        }
+
+        last_old_end = old_pos + length;
+        last_new_end = new_pos + length;
+    }
+
+    copy_new_data(
+        diff_path,
+        &mut new_data_diff,
+        &mut rle,
+        &mut out,
+        header.new_size - last_new_end,
+        &mut buffer,
+        &mut progress,
+        output_callback,
+    )?;
+
+    let out_file = out
+        .into_inner()
+        .map_err(|e| PatcherError::io("Failed to write", out_path)(e.into_error()))?;
+    out_file
+        .sync_all()
+        .map_err(PatcherError::io("Failed to write", out_path))?;
+    progress.finish(output_callback);
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn copy_new_data<F>(
+    diff_path: &Path,
+    new_data_diff: &mut dyn Read,
+    rle: &mut RleDecoder,
+    out: &mut dyn Write,
+    mut length: u64,
+    buffer: &mut [u8],
+    progress: &mut Progress<'_>,
+    output_callback: &mut F,
+) -> Result<(), PatcherError>
+where
+    F: FnMut(ProgressEvent),
+{
+    let corrupt = |e: std::io::Error| invalid(diff_path, format!("Corrupt diff data: {}", e));
+    while length > 0 {
+        let step = length.min(buffer.len() as u64) as usize;
+        new_data_diff
+            .read_exact(&mut buffer[..step])
+            .map_err(corrupt)?;
+        rle.skip(step as u64).map_err(corrupt)?;
+        out.write_all(&buffer[..step])
+            .map_err(|e| invalid(diff_path, format!("Failed to write patched file: {}", e)))?;
+        length -= step as u64;
+        progress.advance(step as u64, output_callback)?;
+    }
+    Ok(())
+}
+
+struct Progress<'a> {
+    done: u64,
+    total: u64,
+    last_reported: u64,
+    cancel: &'a CancelToken,
+}
+
+impl<'a> Progress<'a> {
+    fn new(total: u64, cancel: &'a CancelToken) -> Self {
+        Progress {
+            done: 0,
+            total,
+            last_reported: 0,
+            cancel,
+        }
+    }
+
+    /// Records `bytes` more output, failing if the patch was cancelled.
+    fn advance<F>(&mut self, bytes: u64, output_callback: &mut F) -> Result<(), PatcherError>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        self.cancel.check()?;
+        self.done += bytes;
+        if self.done - self.last_reported >= PROGRESS_STEP {
+            self.last_reported = self.done;
+            output_callback(ProgressEvent::Bytes {
+                done: self.done,
+                total: self.total,
+            });
+        }
+        Ok(())
+    }
+
+    fn finish<F>(&mut self, output_callback: &mut F)
+    where
+        F: FnMut(ProgressEvent),
+    {
+        output_callback(ProgressEvent::Bytes {
+            done: self.done,
+            total: self.total,
+        });
+        output_callback(ProgressEvent::info(format!(
+            "Patched: {} bytes written",
+            self.done
+        )));
+    }
+}
+
+#[derive(Clone, Copy)]
+enum RleRun {
+    Fill(u8),
+    Literal,
+}
+
+/// Decoder for the byte-RLE delta stream. It spans the whole new file:
+/// gaps between covers are skipped, covered bytes are added onto old data.
+struct RleDecoder {
+    ctrl: Box<dyn Read>,
+    code: Box<dyn Read>,
+    run: RleRun,
+    remaining: u64,
+    scratch: Vec<u8>,
+}
+
+impl RleDecoder {
+    fn new(ctrl: Box<dyn Read>, code: Box<dyn Read>) -> Self {
+        RleDecoder {
+            ctrl,
+            code,
+            run: RleRun::Fill(0),
+            remaining: 0,
+            scratch: Vec::new(),
+        }
+    }
+
+    fn next_run(&mut self) -> std::io::Result<()> {
+        let (length, kind) = read_packed_uint(&mut self.ctrl, 2)?;
+        self.run = match kind {
+            0 => RleRun::Fill(0),
+            1 => RleRun::Fill(255),
+            2 => RleRun::Fill(read_u8(&mut self.code)?),
+            _ => RleRun::Literal,
+        };
+        self.remaining = length + 1;
+        Ok(())
+    }
+
+    fn skip(&mut self, mut length: u64) -> std::io::Result<()> {
+        while length > 0 {
+            if self.remaining == 0 {
+                self.next_run()?;
+            }
+            let step = length.min(self.remaining);
+            if let RleRun::Literal = self.run {
+                std::io::copy(&mut (&mut self.code).take(step), &mut std::io::sink())?;
+            }
+            self.remaining -= step;
+            length -= step;
+        }
+        Ok(())
+    }
+
+    fn add(&mut self, data: &mut [u8]) -> std::io::Result<()> {
+        let mut pos = 0;
+        while pos < data.len() {
+            if self.remaining == 0 {
+                self.next_run()?;
+            }
+            let step = (data.len() - pos).min(self.remaining.min(usize::MAX as u64) as usize);
+            let chunk = &mut data[pos..pos + step];
+            match self.run {
+                RleRun::Fill(0) => {}
+                RleRun::Fill(value) => {
+                    for byte in chunk.iter_mut() {
+                        *byte = byte.wrapping_add(value);
+                    }
+                }
+                RleRun::Literal => {
+                    self.scratch.resize(step, 0);
+                    self.code.read_exact(&mut self.scratch)?;
+                    for (byte, delta) in chunk.iter_mut().zip(&self.scratch) {
+                        *byte = byte.wrapping_add(*delta);
+                    }
+                }
+            }
+            self.remaining -= step as u64;
+            pos += step;
+        }
+        Ok(())
+    }
+}
+
+fn read_u8<R: Read + ?Sized>(reader: &mut R) -> std::io::Result<u8> {
+    let mut byte = [0u8; 1];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+/// Reads a big-endian 7-bit varint whose first byte carries `tag_bits`
+/// high tag bits. Returns the value and the tag.
+fn read_packed_uint<R: Read + ?Sized>(reader: &mut R, tag_bits: u32) -> std::io::Result<(u64, u8)> {
+    let first = read_u8(reader)?;
+    let tag = ((first as u16) >> (8 - tag_bits)) as u8;
+    let more = 1u8 << (7 - tag_bits);
+    let mut value = (first & (more - 1)) as u64;
+    if first & more != 0 {
+        loop {
+            let byte = read_u8(reader)?;
+            if value >> 57 != 0 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "varint overflow",
+                ));
+            }
+            value = (value << 7) | (byte & 0x7f) as u64;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+    }
+    Ok((value, tag))
+}
+
+fn invalid(diff_path: &Path, message: impl Into<String>) -> PatcherError {
+    PatcherError::Diff {
+        path: diff_path.to_path_buf(),
+        message: message.into(),
+    }
+}
+
+fn lzma2_dict_size(prop: u8) -> Result<u32, String> {
+    match prop {
+        0..=39 => Ok((2 | (prop as u32 & 1)) << (prop / 2 + 11)),
+        40 => Ok(u32::MAX),
+        _ => Err(format!("Invalid lzma2 dictionary property: {}", prop)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    // A source file, what it becomes, and HDIFF13 diffs between the two
+    // with uncompressed and with zstd sections
+    const OLD: &[u8] = include_bytes!("../testdata/hdiff/old.bin");
+    const NEW: &[u8] = include_bytes!("../testdata/hdiff/new.bin");
+    const PLAIN_DIFF: &[u8] = include_bytes!("../testdata/hdiff/plain.hdiff");
+    const ZSTD_DIFF: &[u8] = include_bytes!("../testdata/hdiff/zstd.hdiff");
+
+    /// A scratch directory holding the old file and `diff`.
+    fn setup(name: &str, diff: &[u8]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("gyeet-hdiff-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("old"), OLD).unwrap();
+        fs::write(dir.join("diff"), diff).unwrap();
+        dir
+    }
+
+    fn apply_in(dir: &Path, cancel: &CancelToken) -> Result<(), PatcherError> {
+        apply(
+            &dir.join("old"),
+            &dir.join("diff"),
+            &dir.join("new"),
+            cancel,
+            &mut |_| {},
+        )
+    }
+
+    #[test]
+    fn applies_uncompressed_diff() {
+        let dir = setup("plain", PLAIN_DIFF);
+        let header = HDiffHeader::read(&dir.join("diff")).unwrap();
+        assert_eq!(header.compress_type, "");
+        assert_eq!(header.old_size, OLD.len() as u64);
+        assert_eq!(header.new_size, NEW.len() as u64);
+
+        apply_in(&dir, &CancelToken::new()).unwrap();
+        assert_eq!(fs::read(dir.join("new")).unwrap(), NEW);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn applies_zstd_diff() {
+        let dir = setup("zstd", ZSTD_DIFF);
+        assert_eq!(
+            HDiffHeader::read(&dir.join("diff")).unwrap().compress_type,
+            "zstd"
+        );
+
+        apply_in(&dir, &CancelToken::new()).unwrap();
+        assert_eq!(fs::read(dir.join("new")).unwrap(), NEW);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_truncated_diff() {
+        let dir = setup("truncated", &PLAIN_DIFF[..PLAIN_DIFF.len() - 100]);
+        let error = apply_in(&dir, &CancelToken::new()).unwrap_err();
+        assert!(matches!(error, PatcherError::Diff { .. }), "{:?}", error);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_wrong_source_size() {
+        let dir = setup("source", PLAIN_DIFF);
+        fs::write(dir.join("old"), &OLD[1..]).unwrap();
+        let error = apply_in(&dir, &CancelToken::new()).unwrap_err();
+        assert!(matches!(error, PatcherError::Diff { .. }), "{:?}", error);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_other_formats() {
+        let dir = setup("format", b"HDIFFSF20&zstd\0rest");
+        let error = HDiffHeader::read(&dir.join("diff")).unwrap_err();
+        assert!(error.to_string().contains("HDIFFSF20"), "{}", error);
+        assert!(!is_hdiff_file(&dir.join("old")));
+        assert!(is_hdiff_file(&dir.join("diff")));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn stops_when_cancelled() {
+        let dir = setup("cancel", PLAIN_DIFF);
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let error = apply_in(&dir, &cancel).unwrap_err();
+        assert!(error.is_cancelled(), "{:?}", error);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reads_tagged_varints() {
+        // Tag bit, continuation bit, then 000001; 0000010 follows
+        let mut data: &[u8] = &[0b1100_0001, 0b0000_0010];
+        assert_eq!(read_packed_uint(&mut data, 1).unwrap(), (130, 1));
+        let mut data: &[u8] = &[0x7f];
+        assert_eq!(read_packed_uint(&mut data, 0).unwrap(), (127, 0));
+    }
+}
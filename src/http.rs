@@ -0,0 +1,92 @@
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::cancel::CancelToken;
+use crate::error::PatcherError;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+/// The blocking client applies this to every read rather than to the whole
+/// request, so it catches stalled transfers without capping large downloads.
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+const MAX_ATTEMPTS: u32 = 4;
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// The HTTP client shared by every request GYeet makes.
+pub fn client() -> &'static Client {
+    static CLIENT: OnceLock<Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .timeout(READ_TIMEOUT)
+            .user_agent(concat!("gyeet/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .expect("Failed to create HTTP client")
+    })
+}
+
+/// Sends the request made by `build`, retrying with exponential backoff
+/// while the failure looks temporary: connection errors, timeouts, 429 and
+/// 5xx responses. Any other response is returned for the caller to check.
+/// Fails with `Cancelled` if `cancel` fires while waiting to retry.
+pub fn send<B>(cancel: &CancelToken, build: B) -> Result<reqwest::Result<Response>, PatcherError>
+where
+    B: Fn(&Client) -> RequestBuilder,
+{
+    let mut attempt = 1;
+    loop {
+        let result = build(client()).send();
+        let retry = match &result {
+            Ok(response) => is_transient_status(response.status()),
+            Err(e) => is_transient(e),
+        };
+        if !retry || attempt >= MAX_ATTEMPTS {
+            return Ok(result);
+        }
+
+        cancel.sleep(backoff(attempt))?;
+        attempt += 1;
+    }
+}
+
+/// How long to wait before retry number `attempt` (starting at 1).
+pub fn backoff(attempt: u32) -> Duration {
+    INITIAL_BACKOFF
+        .saturating_mul(1 << attempt.saturating_sub(1).min(16))
+        .min(MAX_BACKOFF)
+}
+
+/// Whether a request error is worth retrying.
+pub fn is_transient(error: &reqwest::Error) -> bool {
+    error.is_connect() || error.is_timeout() || error.is_request() || error.is_body()
+}
+
+pub fn is_transient_status(status: reqwest::StatusCode) -> bool {
+    status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+}
+
+/// Runs `attempt` against each mirror in order until one succeeds.
+pub fn with_mirrors<T, A>(urls: &[String], what: &str, mut attempt: A) -> Result<T, PatcherError>
+where
+    A: FnMut(&str) -> Result<T, PatcherError>,
+{
+    let mut errors = Vec::new();
+    for url in urls {
+        match attempt(url) {
+            Ok(value) => return Ok(value),
+            Err(e) => errors.push((url.clone(), e)),
+        }
+    }
+
+    match errors.len() {
+        0 => Err(PatcherError::NoMirrors {
+            what: what.to_string(),
+        }),
+        1 => Err(errors.remove(0).1),
+        _ => Err(PatcherError::AllMirrorsFailed {
+            what: what.to_string(),
+            errors,
+        }),
+    }
+}
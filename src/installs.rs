@@ -0,0 +1,70 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Game installs GYeet has installed, updated, repaired or restored, with
+/// the version each was left at. Remembered between runs so a new install
+/// can reuse their files as seeds.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct KnownInstalls {
+    installs: Vec<KnownInstall>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KnownInstall {
+    /// The directory holding VotV.exe.
+    pub dir: PathBuf,
+    pub version: String,
+}
+
+impl KnownInstalls {
+    fn state_path() -> PathBuf {
+        dirs::state_dir()
+            .or_else(dirs::data_local_dir)
+            .unwrap_or_else(std::env::temp_dir)
+            .join("gyeet")
+            .join("installs.json")
+    }
+
+    pub fn load() -> Self {
+        fs::read(Self::state_path())
+            .ok()
+            .and_then(|content| serde_json::from_slice(&content).ok())
+            .unwrap_or_default()
+    }
+
+    /// The installs that are still there.
+    pub fn installs(&self) -> impl Iterator<Item = &KnownInstall> {
+        self.installs.iter().filter(|install| install.dir.is_dir())
+    }
+
+    /// The version `dir` was last left at, if GYeet knows the install.
+    pub fn version_of(&self, dir: &Path) -> Option<&str> {
+        let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+        self.installs
+            .iter()
+            .find(|install| install.dir == dir)
+            .map(|install| install.version.as_str())
+    }
+
+    /// Remembers that `dir` is at `version`, and saves the list.
+    pub fn record(dir: &Path, version: &str) -> io::Result<()> {
+        let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+        let mut known = Self::load();
+        known
+            .installs
+            .retain(|install| install.dir != dir && install.dir.is_dir());
+        known.installs.push(KnownInstall {
+            dir,
+            version: version.to_string(),
+        });
+
+        let state_path = Self::state_path();
+        if let Some(parent) = state_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let content = serde_json::to_vec(&known).expect("install list serializes");
+        fs::write(&state_path, content)
+    }
+}
@@ -1,7 +1,22 @@
 mod ui;
+mod backup;
+mod cache;
+mod cancel;
+mod casync;
 mod config;
+mod disk;
+mod error;
+mod hashes;
+mod hdiff;
+mod http;
+mod installs;
+mod manifest;
+mod mods;
 mod patcher;
+mod progress;
 mod proton;
+mod signature;
+mod staging;
 
 use gtk4::prelude::*;
 use gtk4::Application;
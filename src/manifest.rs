@@ -0,0 +1,255 @@
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::error::PatcherError;
+
+#[derive(Debug, Deserialize)]
+pub struct PatchManifest {
+    pub latest: String,
+    #[serde(rename = "fileHashMap")]
+    pub file_hash_map: HashMap<String, String>,
+    pub patches: HashMap<String, PatchEntries>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PatchInfo {
+    pub url: String,
+    pub sha256: String,
+    /// Version this patch produces. Older manifests omit it, in which case
+    /// the patch upgrades straight to `latest`.
+    #[serde(default, alias = "target")]
+    pub to: Option<String>,
+}
+
+/// A version may list a single patch or several, one per target version.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum PatchEntries {
+    Single(PatchInfo),
+    Multiple(Vec<PatchInfo>),
+}
+
+impl PatchEntries {
+    fn as_slice(&self) -> &[PatchInfo] {
+        match self {
+            PatchEntries::Single(info) => std::slice::from_ref(info),
+            PatchEntries::Multiple(infos) => infos,
+        }
+    }
+}
+
+/// One step of a patch route.
+#[derive(Debug, Clone)]
+pub struct PatchHop {
+    pub from: String,
+    pub to: String,
+    pub patch: PatchInfo,
+}
+
+impl PatchManifest {
+    /// Looks up the version a pak hash belongs to.
+    pub fn version_for_hash(&self, hash: &str) -> Option<&str> {
+        self.file_hash_map
+            .get(hash)
+            .or_else(|| {
+                self.file_hash_map
+                    .iter()
+                    .find(|(known, _)| known.eq_ignore_ascii_case(hash))
+                    .map(|(_, version)| version)
+            })
+            .map(String::as_str)
+    }
+
+    /// Finds the patch whose archive has the given SHA256.
+    pub fn patch_for_hash(&self, sha256: &str) -> Option<PatchHop> {
+        let mut versions: Vec<&String> = self.patches.keys().collect();
+        versions.sort();
+        versions
+            .into_iter()
+            .flat_map(|version| self.patches_from(version))
+            .find(|hop| hop.patch.sha256.eq_ignore_ascii_case(sha256))
+    }
+
+    /// All patches that start at `version`, with their resolved targets.
+    pub fn patches_from(&self, version: &str) -> Vec<PatchHop> {
+        self.patches
+            .get(version)
+            .map(|entries| {
+                entries
+                    .as_slice()
+                    .iter()
+                    .map(|patch| PatchHop {
+                        from: version.to_string(),
+                        to: patch.to.clone().unwrap_or_else(|| self.latest.clone()),
+                        patch: patch.clone(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Finds the shortest sequence of patches leading from `from` to `to`,
+    /// treating every patch as an edge between two versions.
+    pub fn plan_route(&self, from: &str, to: &str) -> Result<Vec<PatchHop>, PatcherError> {
+        if from == to {
+            return Ok(Vec::new());
+        }
+
+        let mut came_from: HashMap<String, PatchHop> = HashMap::new();
+        let mut visited: HashSet<String> = HashSet::from([from.to_string()]);
+        let mut queue = VecDeque::from([from.to_string()]);
+
+        while let Some(version) = queue.pop_front() {
+            let mut hops = self.patches_from(&version);
+            hops.sort_by(|a, b| a.to.cmp(&b.to));
+
+            for hop in hops {
+                if !visited.insert(hop.to.clone()) {
+                    continue;
+                }
+                let next = hop.to.clone();
+                came_from.insert(next.clone(), hop);
+
+                if next == to {
+                    let mut route = Vec::new();
+                    let mut cursor = next;
+                    while let Some(hop) = came_from.remove(&cursor) {
+                        cursor = hop.from.clone();
+                        route.push(hop);
+                    }
+                    route.reverse();
+                    return Ok(route);
+                }
+                queue.push_back(next);
+            }
+        }
+
+        Err(PatcherError::NoRoute {
+            from: from.to_string(),
+            to: to.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 0.6.0 reaches 0.8.0 either directly or through 0.6.5 and 0.7.0.
+    /// 0.8.0 and 0.8.1 patch into each other, and 0.8.0's second patch has
+    /// no target, so it leads to the latest version.
+    const MANIFEST: &str = r#"{
+        "latest": "0.9.0",
+        "fileHashMap": { "ABCDEF": "0.8.0" },
+        "patches": {
+            "0.6.0": [
+                { "url": "https://example.com/a", "sha256": "A1", "to": "0.6.5" },
+                { "url": "https://example.com/b", "sha256": "A2", "to": "0.8.0" }
+            ],
+            "0.6.5": { "url": "https://example.com/c", "sha256": "B1", "to": "0.7.0" },
+            "0.7.0": { "url": "https://example.com/d", "sha256": "C1", "target": "0.8.0" },
+            "0.8.0": [
+                { "url": "https://example.com/e", "sha256": "D1", "to": "0.8.1" },
+                { "url": "https://example.com/f", "sha256": "D2" }
+            ],
+            "0.8.1": { "url": "https://example.com/g", "sha256": "E1", "to": "0.8.0" }
+        }
+    }"#;
+
+    fn manifest() -> PatchManifest {
+        serde_json::from_str(MANIFEST).unwrap()
+    }
+
+    fn steps(route: &[PatchHop]) -> Vec<(&str, &str, &str)> {
+        route
+            .iter()
+            .map(|hop| {
+                (
+                    hop.from.as_str(),
+                    hop.to.as_str(),
+                    hop.patch.sha256.as_str(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reads_single_and_multiple_entries() {
+        let manifest = manifest();
+        assert!(matches!(manifest.patches["0.6.5"], PatchEntries::Single(_)));
+        assert!(matches!(
+            manifest.patches["0.8.0"],
+            PatchEntries::Multiple(_)
+        ));
+        assert_eq!(manifest.patches_from("0.6.5").len(), 1);
+        assert_eq!(manifest.patches_from("0.8.0").len(), 2);
+        assert!(manifest.patches_from("0.9.0").is_empty());
+    }
+
+    #[test]
+    fn missing_target_means_latest() {
+        let hops = manifest().patches_from("0.8.0");
+        assert_eq!(hops[1].patch.to, None);
+        assert_eq!(hops[1].to, "0.9.0");
+        // `target` is read like `to`
+        assert_eq!(manifest().patches_from("0.7.0")[0].to, "0.8.0");
+    }
+
+    #[test]
+    fn finds_versions_and_patches_by_hash() {
+        let manifest = manifest();
+        assert_eq!(manifest.version_for_hash("abcdef"), Some("0.8.0"));
+        assert_eq!(manifest.version_for_hash("123456"), None);
+
+        let hop = manifest.patch_for_hash("d2").unwrap();
+        assert_eq!((hop.from.as_str(), hop.to.as_str()), ("0.8.0", "0.9.0"));
+        assert!(manifest.patch_for_hash("FF").is_none());
+    }
+
+    #[test]
+    fn plans_a_direct_hop() {
+        let route = manifest().plan_route("0.8.0", "0.9.0").unwrap();
+        assert_eq!(steps(&route), [("0.8.0", "0.9.0", "D2")]);
+        assert!(manifest().plan_route("0.9.0", "0.9.0").unwrap().is_empty());
+    }
+
+    #[test]
+    fn plans_the_shortest_route() {
+        let route = manifest().plan_route("0.6.0", "0.9.0").unwrap();
+        assert_eq!(
+            steps(&route),
+            [("0.6.0", "0.8.0", "A2"), ("0.8.0", "0.9.0", "D2")]
+        );
+
+        let route = manifest().plan_route("0.6.5", "0.8.1").unwrap();
+        assert_eq!(
+            steps(&route),
+            [
+                ("0.6.5", "0.7.0", "B1"),
+                ("0.7.0", "0.8.0", "C1"),
+                ("0.8.0", "0.8.1", "D1")
+            ]
+        );
+    }
+
+    #[test]
+    fn plans_routes_through_a_cycle() {
+        let route = manifest().plan_route("0.8.1", "0.9.0").unwrap();
+        assert_eq!(
+            steps(&route),
+            [("0.8.1", "0.8.0", "E1"), ("0.8.0", "0.9.0", "D2")]
+        );
+    }
+
+    #[test]
+    fn reports_unreachable_versions() {
+        for (from, to) in [("0.9.0", "0.6.0"), ("0.8.1", "0.7.0"), ("0.5.0", "0.9.0")] {
+            match manifest().plan_route(from, to) {
+                Err(PatcherError::NoRoute { from: f, to: t }) => {
+                    assert_eq!((f.as_str(), t.as_str()), (from, to))
+                }
+                other => panic!("{} -> {}: {:?}", from, to, other),
+            }
+        }
+    }
+}
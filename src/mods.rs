@@ -0,0 +1,299 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::backup;
+use crate::error::PatcherError;
+use crate::progress::ProgressEvent;
+
+const HELD_FILE: &str = "held.json";
+/// Pak files the game ships with; any other pak in `Content/Paks` is a mod.
+const VANILLA_PAK_FILES: &[&str] = &["VotV-WindowsNoEditor.pak"];
+const PAK_EXTENSIONS: &[&str] = &["pak", "utoc", "ucas"];
+
+/// The pak, utoc and ucas files in `paks_dir` and its subdirectories, such
+/// as `~mods`, that are not part of the game. Paths are relative to
+/// `paks_dir`.
+pub fn find_mod_files(paks_dir: &Path) -> Vec<PathBuf> {
+    let mut found = Vec::new();
+    collect_mod_files(paks_dir, Path::new(""), &mut found);
+    found.sort();
+    found
+}
+
+fn collect_mod_files(paks_dir: &Path, dir: &Path, found: &mut Vec<PathBuf>) {
+    let Ok(items) = fs::read_dir(paks_dir.join(dir)) else {
+        return;
+    };
+    for item in items.flatten() {
+        let path = dir.join(item.file_name());
+        let Ok(file_type) = item.file_type() else {
+            continue;
+        };
+        if file_type.is_dir() {
+            collect_mod_files(paks_dir, &path, found);
+            continue;
+        }
+
+        let is_pak = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| PAK_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()));
+        let is_vanilla = dir.as_os_str().is_empty()
+            && VANILLA_PAK_FILES
+                .iter()
+                .any(|name| item.file_name() == *name);
+        if is_pak && !is_vanilla {
+            found.push(path);
+        }
+    }
+}
+
+/// Mod files moved out of the game while it is patched. The list is saved
+/// next to them before anything moves, so mods held by an update that never
+/// finished are put back by the next one.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HeldMods {
+    #[serde(skip)]
+    dir: PathBuf,
+    paks_dir: PathBuf,
+    files: Vec<PathBuf>,
+}
+
+impl HeldMods {
+    fn dir_for(game_dir: &Path) -> PathBuf {
+        backup::install_data_dir(game_dir, "held_mods")
+    }
+
+    /// Moves `files`, relative to `paks_dir`, to the holding area of the
+    /// install at `game_dir`. Nothing stays moved if this fails.
+    pub fn hold<F>(
+        game_dir: &Path,
+        paks_dir: &Path,
+        files: &[PathBuf],
+        output_callback: &mut F,
+    ) -> Result<Self, PatcherError>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        Self::hold_in(Self::dir_for(game_dir), paks_dir, files, output_callback)
+    }
+
+    fn hold_in<F>(
+        dir: PathBuf,
+        paks_dir: &Path,
+        files: &[PathBuf],
+        output_callback: &mut F,
+    ) -> Result<Self, PatcherError>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        fs::create_dir_all(&dir)
+            .map_err(PatcherError::io("Failed to create mod holding area", &dir))?;
+
+        let held = HeldMods {
+            dir,
+            paks_dir: paks_dir.to_path_buf(),
+            files: files.to_vec(),
+        };
+        let content = serde_json::to_string_pretty(&held).expect("held mod list always serializes");
+        let held_path = held.dir.join(HELD_FILE);
+        fs::write(&held_path, content).map_err(PatcherError::io(
+            "Failed to write held mod list",
+            &held_path,
+        ))?;
+
+        for file in &held.files {
+            let original = held.paks_dir.join(file);
+            if let Err(e) = move_file(&original, &held.dir.join(file)) {
+                let _ = held.release(&mut |_| {});
+                return Err(PatcherError::io("Failed to move aside", &original)(e));
+            }
+            output_callback(ProgressEvent::info(format!(
+                "Moved aside {}",
+                file.display()
+            )));
+        }
+        Ok(held)
+    }
+
+    /// The mods still held for the install at `game_dir`, if an earlier
+    /// update stopped before putting them back.
+    pub fn load(game_dir: &Path) -> Option<Self> {
+        Self::load_from(Self::dir_for(game_dir))
+    }
+
+    fn load_from(dir: PathBuf) -> Option<Self> {
+        let content = fs::read_to_string(dir.join(HELD_FILE)).ok()?;
+        let mut held: HeldMods = serde_json::from_str(&content).ok()?;
+        held.dir = dir;
+        Some(held)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Moves every held file back to where it came from and removes the
+    /// holding area. If a file of the same name has appeared there since,
+    /// it is kept and the held one goes back under a name the game does not
+    /// load, so neither is lost.
+    pub fn release<F>(&self, output_callback: &mut F) -> Result<(), PatcherError>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        for file in &self.files {
+            let held = self.dir.join(file);
+            // Not moved yet when holding failed or was interrupted
+            if !held.exists() {
+                continue;
+            }
+            let mut original = self.paks_dir.join(file);
+            if original.exists() {
+                let kept = unused_name(&original);
+                output_callback(ProgressEvent::Warning(format!(
+                    "{} was replaced while it was moved aside; the old one is now {}",
+                    original.display(),
+                    kept.display()
+                )));
+                original = kept;
+            }
+            move_file(&held, &original)
+                .map_err(PatcherError::io("Failed to put back", &original))?;
+            output_callback(ProgressEvent::info(format!("Put back {}", file.display())));
+        }
+        fs::remove_dir_all(&self.dir).map_err(PatcherError::io(
+            "Failed to remove mod holding area",
+            &self.dir,
+        ))
+    }
+}
+
+/// `path` with `.held` appended, numbered if that exists too.
+fn unused_name(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".held");
+    let mut candidate = PathBuf::from(&name);
+    let mut n = 1;
+    while candidate.exists() {
+        candidate = PathBuf::from(format!("{}.{}", name.to_string_lossy(), n));
+        n += 1;
+    }
+    candidate
+}
+
+/// Renames `src` to `dst`, or copies and deletes it when they are on
+/// different filesystems.
+fn move_file(src: &Path, dst: &Path) -> io::Result<()> {
+    if let Some(parent) = dst.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if fs::rename(src, dst).is_ok() {
+        return Ok(());
+    }
+    backup::clone_or_copy(src, dst)?;
+    fs::remove_file(src)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A scratch directory with a `Paks` folder holding the game's pak and
+    /// `mods`, and the holding area to use.
+    fn setup(name: &str, mods: &[&str]) -> (PathBuf, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("gyeet-mods-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let paks_dir = dir.join("Paks");
+        for file in mods.iter().chain(&["VotV-WindowsNoEditor.pak"]) {
+            let path = paks_dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, file.as_bytes()).unwrap();
+        }
+        (paks_dir, dir.join("held"))
+    }
+
+    fn paths(files: &[&str]) -> Vec<PathBuf> {
+        files.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn finds_mods_but_not_the_game_pak() {
+        let (paks_dir, _) = setup(
+            "find",
+            &[
+                "Cool.pak",
+                "Cool.utoc",
+                "Cool.ucas",
+                "notes.txt",
+                "~mods/Other.PAK",
+                "~mods/VotV-WindowsNoEditor.pak",
+                "~mods/logic/Deep.pak",
+            ],
+        );
+        assert_eq!(
+            find_mod_files(&paks_dir),
+            paths(&[
+                "Cool.pak",
+                "Cool.ucas",
+                "Cool.utoc",
+                "~mods/Other.PAK",
+                "~mods/VotV-WindowsNoEditor.pak",
+                "~mods/logic/Deep.pak",
+            ])
+        );
+        fs::remove_dir_all(paks_dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn holds_and_puts_back_mods() {
+        let (paks_dir, held_dir) = setup("hold", &["Cool.pak", "~mods/Other.pak"]);
+        let files = find_mod_files(&paks_dir);
+        let held = HeldMods::hold_in(held_dir.clone(), &paks_dir, &files, &mut |_| {}).unwrap();
+        assert_eq!(find_mod_files(&paks_dir), Vec::<PathBuf>::new());
+        assert!(paks_dir.join("VotV-WindowsNoEditor.pak").is_file());
+
+        // As after an update that stopped before putting them back
+        drop(held);
+        let held = HeldMods::load_from(held_dir.clone()).unwrap();
+        held.release(&mut |_| {}).unwrap();
+        assert_eq!(find_mod_files(&paks_dir), files);
+        assert_eq!(fs::read(paks_dir.join("Cool.pak")).unwrap(), b"Cool.pak");
+        assert!(!held_dir.exists());
+        fs::remove_dir_all(paks_dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn puts_everything_back_if_holding_fails() {
+        let (paks_dir, held_dir) = setup("fail", &["Cool.pak"]);
+        let files = paths(&["Cool.pak", "Missing.pak"]);
+        assert!(HeldMods::hold_in(held_dir.clone(), &paks_dir, &files, &mut |_| {}).is_err());
+        assert_eq!(find_mod_files(&paks_dir), paths(&["Cool.pak"]));
+        assert!(!held_dir.exists());
+        fs::remove_dir_all(paks_dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn keeps_both_files_when_a_name_is_taken_again() {
+        let (paks_dir, held_dir) = setup("collision", &["Cool.pak"]);
+        let files = paths(&["Cool.pak"]);
+        let held = HeldMods::hold_in(held_dir, &paks_dir, &files, &mut |_| {}).unwrap();
+        fs::write(paks_dir.join("Cool.pak"), b"newer").unwrap();
+
+        let mut warnings = Vec::new();
+        held.release(&mut |event| {
+            if let ProgressEvent::Warning(warning) = event {
+                warnings.push(warning);
+            }
+        })
+        .unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(fs::read(paks_dir.join("Cool.pak")).unwrap(), b"newer");
+        assert_eq!(
+            fs::read(paks_dir.join("Cool.pak.held")).unwrap(),
+            b"Cool.pak"
+        );
+        fs::remove_dir_all(paks_dir.parent().unwrap()).unwrap();
+    }
+}
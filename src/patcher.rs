@@ -1,18 +1,45 @@
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use std::collections::HashMap;
+use std::collections::HashSet;
 use std::fs;
-use std::io::{BufRead, BufReader, Read};
+use std::io::Read;
 use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
-use std::sync::{Arc, Mutex};
-use std::thread;
 
-const PATCH_MANIFEST_URL: &str = "https://votv.dev/patcher_assets/patch_manifest.json";
-const INSTALL_CATALOG_URL: &str = "https://votv.dev/patcher_assets/index_manifest.json";
-const STORE_URL: &str = "https://votv.dev/patcher_assets/256-1024-4096-store";
-const DESYNC_URL: &str =
-    "https://github.com/folbricht/desync/releases/download/v0.9.6/desync_0.9.6_linux_amd64.tar.gz";
+use crate::backup::{self, Snapshot};
+use crate::cache::{CacheUsage, ChunkCache, DownloadCache};
+use crate::cancel::CancelToken;
+use crate::casync::{self, EntryKind};
+use crate::config::Config;
+use crate::disk::{self, SpaceCheck};
+use crate::error::PatcherError;
+use crate::hashes::{FileStamp, HashCache};
+use crate::hdiff;
+use crate::http;
+use crate::installs::{KnownInstall, KnownInstalls};
+use crate::manifest::{PatchHop, PatchManifest};
+use crate::mods::{self, HeldMods};
+use crate::progress::{self, ProgressEvent, RateMeter, Stage};
+use crate::signature;
+use crate::staging::StagingDir;
+
+const MAX_DOWNLOAD_ATTEMPTS: u32 = 5;
+const HASH_BUFFER_SIZE: usize = 4 * 1024 * 1024;
+const PAK_RELATIVE_PATH: &str = "VotV/Content/Paks/VotV-WindowsNoEditor.pak";
+/// Patch archives are compressed; their contents are assumed to take at most
+/// this many times the archive's size once extracted.
+const EXTRACTED_PATCH_FACTOR: u64 = 4;
+/// Files patch payloads ship for applying the diff by hand, which GYeet
+/// does natively instead.
+const PATCH_TOOL_FILES: &[&str] = &[
+    "apply_patch.sh",
+    "apply_patch.bat",
+    "hpatchz",
+    "hpatchz.exe",
+];
+/// Extra files found by Verify & Repair that are listed one by one.
+const MAX_LISTED_EXTRA_FILES: usize = 20;
+/// Chunks fetched at once while installing.
+const CHUNK_DOWNLOAD_THREADS: usize = 16;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GameVersion {
@@ -21,582 +48,2535 @@ pub struct GameVersion {
     pub link: String,
 }
 
-#[derive(Debug, Deserialize)]
-struct PatchManifest {
-    latest: String,
-    #[serde(rename = "fileHashMap")]
-    file_hash_map: HashMap<String, String>,
-    patches: HashMap<String, PatchInfo>,
+/// Outcome of an update check: what is installed and how to get to the target.
+#[derive(Debug, Clone)]
+pub struct UpdateReport {
+    pub game_dir: PathBuf,
+    pub pak_path: PathBuf,
+    pub pak_hash: String,
+    pub current_version: String,
+    pub latest_version: String,
+    pub target_version: String,
+    pub route: Vec<PlannedPatch>,
+    /// Why no patch route could be found, if the game is not up to date.
+    pub unavailable: Option<PatcherError>,
+    /// Pak files next to the main pak that are not part of the game,
+    /// relative to its directory. They are moved aside while patching.
+    pub mod_files: Vec<PathBuf>,
 }
 
-#[derive(Debug, Deserialize)]
-struct PatchInfo {
-    url: String,
-    sha256: String,
+/// Where the patches for an update come from.
+#[derive(Debug, Clone)]
+pub enum PatchSource {
+    /// Download whatever the online manifest says leads to `target_version`,
+    /// or to the latest version.
+    Online { target_version: Option<String> },
+    /// Apply a patch archive already on disk, checked against a local
+    /// manifest or the last downloaded one. Needs no network.
+    Local {
+        archive: PathBuf,
+        manifest: Option<PathBuf>,
+    },
 }
 
-pub struct Patcher {}
+#[derive(Debug, Clone)]
+pub struct PlannedPatch {
+    pub hop: PatchHop,
+    /// Download size reported by a HEAD request, if the server sent one.
+    pub size: Option<u64>,
+    /// Archive to apply instead of downloading one.
+    pub local_archive: Option<PathBuf>,
+}
+
+impl UpdateReport {
+    pub fn is_up_to_date(&self) -> bool {
+        self.current_version == self.target_version
+    }
+
+    pub fn patch_available(&self) -> bool {
+        !self.route.is_empty()
+    }
+
+    pub fn total_download_size(&self) -> Option<u64> {
+        self.route.iter().map(|planned| planned.size).sum()
+    }
+
+    /// Human readable summary in the spirit of YeetPatch.sh's PATCH INFO box.
+    pub fn summary(&self) -> String {
+        let mut lines = vec![
+            format!("Pak SHA-256    : {}", self.pak_hash),
+            format!("Current build  : {}", self.current_version),
+            format!("Latest build   : {}", self.latest_version),
+            format!("Upgrade target : {}", self.target_version),
+        ];
+        if !self.mod_files.is_empty() {
+            lines.push(format!(
+                "Mod files      : {} (moved aside while patching)",
+                self.mod_files.len()
+            ));
+        }
+
+        if self.is_up_to_date() {
+            lines.push("Already up to date.".to_string());
+            return lines.join("\n");
+        }
+        if let Some(reason) = &self.unavailable {
+            lines.push(format!("No patch available: {}", reason));
+            return lines.join("\n");
+        }
+
+        for planned in &self.route {
+            lines.push(String::new());
+            lines.push(format!(
+                "Patch          : {} -> {}",
+                planned.hop.from, planned.hop.to
+            ));
+            match &planned.local_archive {
+                Some(archive) => lines.push(format!("Patch file     : {}", archive.display())),
+                None => lines.push(format!("Download URL   : {}", planned.hop.patch.url)),
+            }
+            lines.push(format!("File SHA-256   : {}", planned.hop.patch.sha256));
+            lines.push(format!(
+                "Download size  : {}",
+                planned
+                    .size
+                    .map(|s| format!("{} bytes", s))
+                    .unwrap_or_else(|| "unknown".to_string())
+            ));
+        }
+        if self.route.len() > 1 {
+            lines.push(String::new());
+            lines.push(format!(
+                "Total download : {}",
+                self.total_download_size()
+                    .map(|s| format!("{} bytes", s))
+                    .unwrap_or_else(|| "unknown".to_string())
+            ));
+        }
+        lines.join("\n")
+    }
+}
+
+/// How a file found by Verify & Repair compares with the index.
+enum EntryStatus {
+    Intact,
+    Missing,
+    Modified,
+}
+
+/// What bringing an install in line with an index changed.
+#[derive(Debug, Default)]
+struct Synced {
+    /// Entries written because they were missing or modified.
+    written: usize,
+    /// Paths deleted because only the replaced version has them.
+    removed: usize,
+}
+
+pub struct Patcher {
+    config: Config,
+}
+
+enum DownloadError {
+    /// Worth retrying with a Range request from where we stopped
+    Interrupted(PatcherError),
+    Fatal(PatcherError),
+}
+
+/// Feeds everything written to it into a SHA256 hasher.
+struct HashWriter<'a>(&'a mut Sha256);
+
+impl std::io::Write for HashWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
 
 impl Patcher {
-    pub fn new() -> Self {
-        Patcher {}
+    pub fn new(config: Config) -> Self {
+        Patcher { config }
     }
 
-    pub fn run_update<F>(&self, votv_exe_path: &str, mut output_callback: F) -> Result<i32, String>
+    /// Works out what an update would do without downloading or changing
+    /// anything: identifies the installed version and plans the patch route.
+    /// The pak hash from an earlier check is reused if the pak has not
+    /// changed since, unless `force_rehash` is set.
+    pub fn check_update<F>(
+        &self,
+        votv_exe_path: &str,
+        source: &PatchSource,
+        force_rehash: bool,
+        cancel: &CancelToken,
+        mut output_callback: F,
+    ) -> Result<UpdateReport, PatcherError>
     where
-        F: FnMut(String),
+        F: FnMut(ProgressEvent),
     {
-        output_callback("Checking VotV.exe path...".to_string());
+        self.prepare_update(
+            votv_exe_path,
+            source,
+            force_rehash,
+            cancel,
+            &mut output_callback,
+        )
+        .map(|(report, _)| report)
+    }
 
-        let exe_path = Path::new(votv_exe_path);
-        if !exe_path.exists() {
-            return Err(format!("VotV.exe not found: {}", votv_exe_path));
-        }
+    /// Patches the game from `source`, applying as many patches as needed.
+    /// Returns the version the game was verified to be at afterwards, which
+    /// is also reported as the final `Finished` event.
+    pub fn run_update<F>(
+        &self,
+        votv_exe_path: &str,
+        source: &PatchSource,
+        cancel: &CancelToken,
+        mut output_callback: F,
+    ) -> Result<String, PatcherError>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        let result = self.update(votv_exe_path, source, cancel, &mut output_callback);
+        finish(result, &mut output_callback)
+    }
 
-        if exe_path.file_name().and_then(|n| n.to_str()) != Some("VotV.exe") {
-            return Err("Selected file must be VotV.exe".to_string());
+    fn update<F>(
+        &self,
+        votv_exe_path: &str,
+        source: &PatchSource,
+        cancel: &CancelToken,
+        output_callback: &mut F,
+    ) -> Result<String, PatcherError>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        // Mods held by an update that was interrupted go back first, so they
+        // are seen and held again below
+        if let Ok(game_dir) = Self::game_dir_for(Path::new(votv_exe_path)) {
+            if let Some(held) = HeldMods::load(game_dir) {
+                output_callback(ProgressEvent::info(
+                    "Putting back mods moved aside by an earlier update...",
+                ));
+                held.release(output_callback)?;
+            }
         }
 
-        // Find the .pak file
-        let game_dir = exe_path.parent().ok_or("Invalid exe path")?;
-        let pak_path = game_dir.join("VotV/Content/Paks/VotV-WindowsNoEditor.pak");
+        let (report, manifest) =
+            self.prepare_update(votv_exe_path, source, false, cancel, output_callback)?;
 
-        if !pak_path.exists() {
-            return Err(format!("Pak file not found: {}", pak_path.display()));
+        if report.is_up_to_date() {
+            output_callback(ProgressEvent::info(format!(
+                "Already at version {}!",
+                report.target_version
+            )));
+            Self::record_install(&report.game_dir, &report.target_version, output_callback);
+            return Ok(report.target_version);
+        }
+        if let Some(reason) = report.unavailable {
+            return Err(reason);
         }
 
-        output_callback(format!("Hashing pak file: {}", pak_path.display()));
-        let pak_hash = Self::sha256_file(&pak_path)?;
-        output_callback(format!("SHA256: {}", pak_hash));
+        let game_dir = report.game_dir.as_path();
+        let pak_path = report.pak_path.as_path();
+        let cache = self.download_cache();
+        Self::check_update_space(&report, &cache, output_callback)?;
+        cancel.check()?;
+
+        // Keep a copy of the current build so a failed update can be undone
+        output_callback(ProgressEvent::StageStarted(Stage::Backup));
+        output_callback(ProgressEvent::info("Backing up current version..."));
+        let snapshot = Snapshot::create(
+            game_dir,
+            &report.current_version,
+            &[pak_path],
+            output_callback,
+        )?;
+
+        // Patch with only vanilla files in place; mods go back afterwards
+        let held = if report.mod_files.is_empty() {
+            None
+        } else {
+            output_callback(ProgressEvent::info(format!(
+                "Moving {} mod files aside while patching...",
+                report.mod_files.len()
+            )));
+            let paks_dir = pak_path.parent().unwrap_or(game_dir);
+            Some(HeldMods::hold(
+                game_dir,
+                paks_dir,
+                &report.mod_files,
+                output_callback,
+            )?)
+        };
+        output_callback(ProgressEvent::StageFinished(Stage::Backup));
+
+        let result = match Self::apply_route(
+            &report.route,
+            &manifest,
+            pak_path,
+            &cache,
+            cancel,
+            output_callback,
+        ) {
+            Ok(()) => Ok(report.target_version),
+            Err(e) => {
+                output_callback(ProgressEvent::error(format!("Update failed: {}", e)));
+                output_callback(ProgressEvent::StageStarted(Stage::Restore));
+                output_callback(ProgressEvent::info(format!(
+                    "Restoring version {}...",
+                    snapshot.version
+                )));
+                match snapshot.restore(output_callback) {
+                    Ok(()) => Err(PatcherError::RolledBack {
+                        error: Box::new(e),
+                        version: snapshot.version,
+                    }),
+                    Err(restore_error) => Err(PatcherError::RollbackFailed {
+                        error: Box::new(e),
+                        restore_error: Box::new(restore_error),
+                    }),
+                }
+            }
+        };
+
+        if let Some(held) = held {
+            output_callback(ProgressEvent::info("Putting back mod files..."));
+            if let Err(e) = held.release(output_callback) {
+                output_callback(ProgressEvent::Warning(format!(
+                    "Could not put back all mod files: {}. The rest are in {} and the next update puts them back.",
+                    e,
+                    held.dir().display()
+                )));
+            }
+        }
 
-        // Fetch patch manifest
-        output_callback("Fetching patch manifest...".to_string());
-        let manifest = Self::fetch_patch_manifest()?;
+        if let Ok(version) = &result {
+            Self::record_install(game_dir, version, output_callback);
+            output_callback(ProgressEvent::success("Update complete!"));
+        }
+        result
+    }
 
-        // Determine current version
-        let current_version = manifest
-            .file_hash_map
-            .get(&pak_hash)
-            .ok_or("Game version not recognized. Your game might be modded or corrupted.")?;
+    /// Puts back the files saved before the last update of this install.
+    /// Returns the restored version.
+    pub fn restore_previous<F>(
+        &self,
+        votv_exe_path: &str,
+        mut output_callback: F,
+    ) -> Result<String, PatcherError>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        let result = Self::restore(votv_exe_path, &mut output_callback);
+        finish(result, &mut output_callback)
+    }
 
-        output_callback(format!("Current version: {}", current_version));
-        output_callback(format!("Latest version: {}", manifest.latest));
+    fn restore<F>(votv_exe_path: &str, output_callback: &mut F) -> Result<String, PatcherError>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        let exe_path = Path::new(votv_exe_path);
+        let game_dir = Self::game_dir_for(exe_path)?;
+
+        let snapshot = Snapshot::load(game_dir).ok_or_else(|| PatcherError::NoBackup {
+            game_dir: game_dir.to_path_buf(),
+        })?;
+        output_callback(ProgressEvent::StageStarted(Stage::Restore));
+        output_callback(ProgressEvent::info(format!(
+            "Restoring version {}...",
+            snapshot.version
+        )));
+        snapshot.restore(output_callback)?;
+        output_callback(ProgressEvent::StageFinished(Stage::Restore));
+
+        Self::record_install(game_dir, &snapshot.version, output_callback);
+        output_callback(ProgressEvent::success(format!(
+            "Restored version {}!",
+            snapshot.version
+        )));
+        Ok(snapshot.version)
+    }
 
-        if current_version == &manifest.latest {
-            output_callback("Already at latest version!".to_string());
-            return Ok(0);
+    fn apply_route<F>(
+        route: &[PlannedPatch],
+        manifest: &PatchManifest,
+        pak_path: &Path,
+        cache: &DownloadCache,
+        cancel: &CancelToken,
+        output_callback: &mut F,
+    ) -> Result<(), PatcherError>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        for (index, planned) in route.iter().enumerate() {
+            cancel.check()?;
+            let hop = &planned.hop;
+            output_callback(ProgressEvent::info(format!(
+                "Applying patch {}/{}: {} -> {}",
+                index + 1,
+                route.len(),
+                hop.from,
+                hop.to
+            )));
+            Self::apply_patch(planned, pak_path, cache, cancel, output_callback)?;
+
+            // Each patch must land on the exact build the manifest promises;
+            // the next patch only applies cleanly to that build
+            output_callback(ProgressEvent::StageStarted(Stage::Verify));
+            output_callback(ProgressEvent::info(format!(
+                "Verifying version {}...",
+                hop.to
+            )));
+            let hash = Self::hash_and_remember(pak_path, cancel, output_callback)?;
+            match manifest.version_for_hash(&hash) {
+                Some(version) if version == hop.to => {
+                    output_callback(ProgressEvent::success(format!(
+                        "Now at version {}",
+                        version
+                    )));
+                    output_callback(ProgressEvent::StageFinished(Stage::Verify));
+                }
+                found => {
+                    return Err(PatcherError::VersionMismatch {
+                        expected: hop.to.clone(),
+                        found: found.map(str::to_string),
+                        hash,
+                    });
+                }
+            }
         }
+        Ok(())
+    }
 
-        // Get patch info
-        let patch_info = manifest.patches.get(current_version).ok_or(format!(
-            "No patch available for version {}",
-            current_version
-        ))?;
+    fn prepare_update<F>(
+        &self,
+        votv_exe_path: &str,
+        source: &PatchSource,
+        force_rehash: bool,
+        cancel: &CancelToken,
+        output_callback: &mut F,
+    ) -> Result<(UpdateReport, PatchManifest), PatcherError>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        output_callback(ProgressEvent::StageStarted(Stage::Check));
+        output_callback(ProgressEvent::info("Checking VotV.exe path..."));
+
+        let exe_path = Path::new(votv_exe_path);
+        if !exe_path.exists() {
+            return Err(PatcherError::InvalidGamePath {
+                path: exe_path.to_path_buf(),
+                reason: "VotV.exe not found",
+            });
+        }
 
-        output_callback(format!("Downloading patch from: {}", patch_info.url));
-        output_callback(format!("Expected SHA256: {}", patch_info.sha256));
+        // Find the .pak file
+        let game_dir = Self::game_dir_for(exe_path)?;
+        let pak_path = game_dir.join(PAK_RELATIVE_PATH);
 
-        // Download patch
-        let patch_data = Self::download_file(&patch_info.url, &mut output_callback)?;
+        if !pak_path.exists() {
+            return Err(PatcherError::PakNotFound { path: pak_path });
+        }
 
-        // Verify hash
-        output_callback("Verifying patch integrity...".to_string());
-        let patch_hash = Self::sha256_bytes(&patch_data);
-        if patch_hash.to_uppercase() != patch_info.sha256.to_uppercase() {
-            return Err("Patch SHA256 mismatch! Download may be corrupted.".to_string());
+        let mod_files = pak_path
+            .parent()
+            .map(mods::find_mod_files)
+            .unwrap_or_default();
+        if !mod_files.is_empty() {
+            output_callback(ProgressEvent::Warning(format!(
+                "Found {} pak files that are not part of the game:",
+                mod_files.len()
+            )));
+            for file in &mod_files {
+                output_callback(ProgressEvent::info(format!("  {}", file.display())));
+            }
         }
-        output_callback("Patch verified successfully".to_string());
 
-        // Extract patch
-        let target_parent = game_dir.parent().ok_or("Invalid game directory")?;
-        output_callback(format!("Extracting patch to: {}", target_parent.display()));
+        let pak_hash = Self::pak_hash(&pak_path, force_rehash, cancel, output_callback)?;
+        output_callback(ProgressEvent::info(format!("SHA256: {}", pak_hash)));
+
+        let manifest = match source {
+            PatchSource::Online { .. } => {
+                output_callback(ProgressEvent::info("Fetching patch manifest..."));
+                self.fetch_patch_manifest(cancel, output_callback)?
+            }
+            PatchSource::Local {
+                manifest: Some(path),
+                ..
+            } => {
+                output_callback(ProgressEvent::info(format!(
+                    "Loading patch manifest: {}",
+                    path.display()
+                )));
+                self.load_local_manifest(path, output_callback)?
+            }
+            PatchSource::Local { manifest: None, .. } => {
+                output_callback(ProgressEvent::info(
+                    "Using the last downloaded patch manifest",
+                ));
+                self.load_cached_manifest(output_callback)?
+            }
+        };
+
+        // Determine current version
+        let current_version = manifest
+            .version_for_hash(&pak_hash)
+            .ok_or_else(|| PatcherError::UnknownVersion {
+                hash: pak_hash.clone(),
+            })?
+            .to_string();
+
+        output_callback(ProgressEvent::info(format!(
+            "Current version: {} (main pak unmodified)",
+            current_version
+        )));
+        output_callback(ProgressEvent::info(format!(
+            "Latest version: {}",
+            manifest.latest
+        )));
+
+        let mut report = UpdateReport {
+            game_dir: game_dir.to_path_buf(),
+            pak_path,
+            pak_hash,
+            current_version,
+            latest_version: manifest.latest.clone(),
+            target_version: manifest.latest.clone(),
+            route: Vec::new(),
+            unavailable: None,
+            mod_files,
+        };
+
+        match source {
+            PatchSource::Online { target_version } => {
+                if let Some(target) = target_version {
+                    output_callback(ProgressEvent::info(format!("Target version: {}", target)));
+                    report.target_version = target.clone();
+                }
+                Self::plan_online_route(&mut report, &manifest, output_callback);
+            }
+            PatchSource::Local { archive, .. } => {
+                Self::plan_local_patch(&mut report, &manifest, archive, cancel, output_callback)?;
+            }
+        }
+        output_callback(ProgressEvent::StageFinished(Stage::Check));
 
-        Self::extract_7z(&patch_data, target_parent, &mut output_callback)?;
+        Ok((report, manifest))
+    }
 
-        // Find and run apply_patch.sh
-        output_callback("Looking for apply_patch.sh...".to_string());
-        let patch_script = Self::find_patch_script(target_parent)?;
-        output_callback(format!("Found: {}", patch_script.display()));
+    /// Plans the chain of patches to download to reach the report's target.
+    fn plan_online_route<F>(
+        report: &mut UpdateReport,
+        manifest: &PatchManifest,
+        output_callback: &mut F,
+    ) where
+        F: FnMut(ProgressEvent),
+    {
+        if report.is_up_to_date() {
+            return;
+        }
 
-        output_callback("Applying patch...".to_string());
-        Self::run_patch_script(&patch_script, &mut output_callback)?;
+        let route = match manifest.plan_route(&report.current_version, &report.target_version) {
+            Ok(route) => route,
+            Err(e) => {
+                output_callback(ProgressEvent::info(e.to_string()));
+                report.unavailable = Some(e);
+                return;
+            }
+        };
+        let path: Vec<&str> = std::iter::once(report.current_version.as_str())
+            .chain(route.iter().map(|hop| hop.to.as_str()))
+            .collect();
+        output_callback(ProgressEvent::info(format!(
+            "Planned route: {} ({} patch{})",
+            path.join(" -> "),
+            route.len(),
+            if route.len() == 1 { "" } else { "es" }
+        )));
+
+        for hop in route {
+            let size = Self::remote_size(&hop.patch.url);
+            output_callback(ProgressEvent::info(format!(
+                "  {} -> {}: {} ({})",
+                hop.from,
+                hop.to,
+                hop.patch.url,
+                size.map(|s| format!("{} bytes", s))
+                    .unwrap_or_else(|| "unknown size".to_string())
+            )));
+            report.route.push(PlannedPatch {
+                hop,
+                size,
+                local_archive: None,
+            });
+        }
+        match report.total_download_size() {
+            Some(total) => output_callback(ProgressEvent::info(format!(
+                "Total download size: {} bytes",
+                total
+            ))),
+            None => output_callback(ProgressEvent::info("Total download size: unknown")),
+        }
+    }
 
-        output_callback("Cleaning up temporary files...".to_string());
-        // Clean up extracted files
-        if let Some(patch_dir) = patch_script.parent() {
-            let _ = fs::remove_dir_all(patch_dir);
+    /// Matches a local archive against the manifest by its hash and checks
+    /// that it applies to the installed version.
+    fn plan_local_patch<F>(
+        report: &mut UpdateReport,
+        manifest: &PatchManifest,
+        archive: &Path,
+        cancel: &CancelToken,
+        output_callback: &mut F,
+    ) -> Result<(), PatcherError>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        let size = fs::metadata(archive)
+            .map_err(PatcherError::io("Cannot read patch file", archive))?
+            .len();
+
+        output_callback(ProgressEvent::info(format!(
+            "Hashing patch file: {}",
+            archive.display()
+        )));
+        let archive_hash = Self::sha256_file(archive, cancel, output_callback)?;
+        output_callback(ProgressEvent::info(format!("SHA256: {}", archive_hash)));
+
+        let hop =
+            manifest
+                .patch_for_hash(&archive_hash)
+                .ok_or_else(|| PatcherError::UnknownPatch {
+                    path: archive.to_path_buf(),
+                    hash: archive_hash.clone(),
+                })?;
+        output_callback(ProgressEvent::info(format!(
+            "Patch file upgrades {} -> {}",
+            hop.from, hop.to
+        )));
+
+        report.target_version = hop.to.clone();
+        if report.is_up_to_date() {
+            return Ok(());
+        }
+        if hop.from != report.current_version {
+            let reason = PatcherError::WrongBaseVersion {
+                patch_from: hop.from.clone(),
+                patch_to: hop.to.clone(),
+                installed: report.current_version.clone(),
+            };
+            output_callback(ProgressEvent::info(reason.to_string()));
+            report.unavailable = Some(reason);
+            return Ok(());
         }
 
-        output_callback("Update complete!".to_string());
-        Ok(0)
+        report.route.push(PlannedPatch {
+            hop,
+            size: Some(size),
+            local_archive: Some(archive.to_path_buf()),
+        });
+        Ok(())
     }
 
+    /// Installs `version` into `install_dir` from the chunk store. Returns
+    /// the installed version, which is also reported as the final `Finished`
+    /// event.
     pub fn run_install<F>(
         &self,
         install_dir: &str,
         version: &GameVersion,
+        cancel: &CancelToken,
         mut output_callback: F,
-    ) -> Result<i32, String>
+    ) -> Result<String, PatcherError>
     where
-        F: FnMut(String),
+        F: FnMut(ProgressEvent),
     {
-        output_callback(format!("Installing VotV version: {}", version.name));
-        output_callback(format!("Target directory: {}", install_dir));
-        output_callback(format!("Index URL: {}", version.link));
-        output_callback(format!("Store URL: {}", STORE_URL));
+        let result = self.install(install_dir, version, cancel, &mut output_callback);
+        finish(result, &mut output_callback)
+    }
 
-        // Ensure desync binary exists
-        output_callback("Checking for desync binary...".to_string());
-        let desync_bin = Self::ensure_desync(&mut output_callback)?;
-        output_callback(format!("Using desync: {}", desync_bin.display()));
+    fn install<F>(
+        &self,
+        install_dir: &str,
+        version: &GameVersion,
+        cancel: &CancelToken,
+        output_callback: &mut F,
+    ) -> Result<String, PatcherError>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        output_callback(ProgressEvent::info(format!(
+            "Installing VotV version: {}",
+            version.name
+        )));
+        output_callback(ProgressEvent::info(format!(
+            "Target directory: {}",
+            install_dir
+        )));
+        output_callback(ProgressEvent::info(format!("Index URL: {}", version.link)));
+        output_callback(ProgressEvent::info(format!(
+            "Store URL: {}",
+            self.config.store_urls.join(", ")
+        )));
 
         // Create install directory
-        fs::create_dir_all(install_dir)
-            .map_err(|e| format!("Failed to create install directory: {}", e))?;
+        let created_install_dir = !Path::new(install_dir).exists();
+        fs::create_dir_all(install_dir).map_err(PatcherError::io(
+            "Failed to create install directory",
+            Path::new(install_dir),
+        ))?;
 
-        // Download .caidx file
-        output_callback("Downloading index file...".to_string());
-        let caidx_data = Self::download_file(&version.link, &mut output_callback)?;
+        let staging = StagingDir::create("install")?;
+        let result = self.download_and_unpack(
+            version,
+            install_dir,
+            staging.path(),
+            cancel,
+            output_callback,
+        );
 
-        // Save to temp file
-        let temp_dir = std::env::temp_dir().join(format!("gyeet_install_{}", std::process::id()));
-        fs::create_dir_all(&temp_dir)
-            .map_err(|e| format!("Failed to create temp directory: {}", e))?;
+        // Cleanup
+        output_callback(ProgressEvent::info("Cleaning up temporary files..."));
+        drop(staging);
+
+        if let Err(e) = result {
+            if e.is_cancelled() {
+                if created_install_dir {
+                    output_callback(ProgressEvent::info("Removing the partial install..."));
+                    let _ = fs::remove_dir_all(install_dir);
+                } else {
+                    output_callback(ProgressEvent::Warning(format!(
+                        "{} holds an incomplete install",
+                        install_dir
+                    )));
+                }
+            }
+            return Err(e);
+        }
 
-        let caidx_path = temp_dir.join("index.caidx");
-        fs::write(&caidx_path, &caidx_data)
-            .map_err(|e| format!("Failed to write caidx file: {}", e))?;
+        Self::record_install(Path::new(install_dir), &version.name, output_callback);
+        output_callback(ProgressEvent::success("Installation complete!"));
+        output_callback(ProgressEvent::info(format!(
+            "Game installed to: {}",
+            install_dir
+        )));
 
-        output_callback(format!("Index saved to: {}", caidx_path.display()));
+        Ok(version.name.clone())
+    }
 
-        // Run desync untar
-        output_callback("Running desync to extract game files...".to_string());
-        output_callback("This may take a while depending on your connection...".to_string());
+    /// Fetches the index of `version` into `temp_dir` and unpacks the game
+    /// files it lists into `install_dir`. Over an earlier copy, only the
+    /// files that do not match the index are written.
+    fn download_and_unpack<F>(
+        &self,
+        version: &GameVersion,
+        install_dir: &str,
+        temp_dir: &Path,
+        cancel: &CancelToken,
+        output_callback: &mut F,
+    ) -> Result<(), PatcherError>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        let caidx_path = self.fetch_index(version, temp_dir, cancel, output_callback)?;
+
+        // The archive the index describes holds every game file
+        let index = casync::Index::load(&caidx_path)?;
+        let game_size = index.size();
+        output_callback(ProgressEvent::info(format!(
+            "Game files: {}",
+            disk::format_size(game_size)
+        )));
+
+        let install_dir = Path::new(install_dir);
+        let chunk_cache = ChunkCache::new(&self.config);
+        let seeds = self.seed_installs(None);
+        // Files of an earlier copy that already match stay in place;
+        // sync_with_index checks the room the others need once it knows them
+        let reinstall = fs::read_dir(install_dir).is_ok_and(|mut items| items.next().is_some());
+        let mut space = SpaceCheck::new();
+        if !reinstall {
+            space.need(install_dir, game_size);
+        }
+        // The cache evicts as it fills, so it never takes more than its limit
+        if let Some(chunk_cache) = &chunk_cache {
+            space.need(chunk_cache.dir(), game_size.min(chunk_cache.limit()));
+        }
+        // Seed indexes the download cache cannot keep go to temp_dir, and
+        // are about the size of this one
+        let index_size = fs::metadata(&caidx_path).map_or(0, |m| m.len());
+        space.need(temp_dir, index_size * seeds.len() as u64);
+        space.check()?;
+
+        if let Some(chunk_cache) = &chunk_cache {
+            output_callback(ProgressEvent::info(format!(
+                "Chunk cache: {}",
+                chunk_cache.dir().display()
+            )));
+        }
+        let mut store = casync::ChunkStore::new(
+            &index,
+            &self.config.store_urls,
+            chunk_cache.as_ref(),
+            cancel,
+        );
+        let seed_downloads = self.add_seeds(
+            &mut store,
+            &seeds,
+            chunk_cache.as_ref(),
+            temp_dir,
+            cancel,
+            output_callback,
+        )?;
+
+        store.writing_to(install_dir);
+        if reinstall {
+            output_callback(ProgressEvent::info(format!(
+                "{} is not empty, keeping the files that already match",
+                install_dir.display()
+            )));
+            let result = Self::sync_with_index(
+                install_dir,
+                &caidx_path,
+                &store,
+                &HashSet::new(),
+                Stage::Unpack,
+                cancel,
+                output_callback,
+            );
+            Self::trim_chunk_cache(chunk_cache.as_ref(), output_callback);
+            let synced = result?;
+            output_callback(ProgressEvent::success(format!(
+                "Wrote {} files, the others already matched",
+                synced.written
+            )));
+        } else {
+            output_callback(ProgressEvent::StageStarted(Stage::Unpack));
+            output_callback(ProgressEvent::info(
+                "Fetching game files from the chunk store...",
+            ));
+            output_callback(ProgressEvent::info(
+                "This may take a while depending on your connection...",
+            ));
 
-        Self::run_desync(&desync_bin, &caidx_path, install_dir, &mut output_callback)?;
-        output_callback("desync command complete.".to_string());
+            let mut archive = casync::Archive::new(&store, &caidx_path);
+            let mut meter = RateMeter::new(game_size);
+            let mut files = 0;
+            let result = archive.walk_prefetching(CHUNK_DOWNLOAD_THREADS, |archive, entry| {
+                for warning in store.take_warnings() {
+                    output_callback(ProgressEvent::Warning(warning));
+                }
+                let start = match entry.kind {
+                    EntryKind::File { offset, .. } => {
+                        files += 1;
+                        offset
+                    }
+                    _ => 0,
+                };
+                let local = install_dir.join(&entry.path);
+                Self::write_entry(archive, entry, &local, |done| {
+                    if let Some(event) = meter.update(start + done) {
+                        output_callback(event);
+                    }
+                })
+            });
+            for warning in store.take_warnings() {
+                output_callback(ProgressEvent::Warning(warning));
+            }
+            Self::trim_chunk_cache(chunk_cache.as_ref(), output_callback);
+            result?;
+
+            output_callback(ProgressEvent::success(format!(
+                "Unpacked {} files in {}",
+                files,
+                progress::format_duration(meter.elapsed())
+            )));
+            output_callback(ProgressEvent::StageFinished(Stage::Unpack));
+        }
+        output_callback(ProgressEvent::info(format!(
+            "From other installs: {}, from the chunk cache: {}, downloaded: {}",
+            disk::format_size(store.seeded()),
+            disk::format_size(store.cached()),
+            disk::format_size(store.downloaded() + seed_downloads)
+        )));
+        Ok(())
+    }
 
-        // Cleanup
-        output_callback("Cleaning up temporary files...".to_string());
-        let _ = fs::remove_dir_all(&temp_dir);
+    /// Remembers that the install in `dir` is at `version`, for seeding
+    /// and version detection. Not being able to is only worth a warning.
+    fn record_install<F>(dir: &Path, version: &str, output_callback: &mut F)
+    where
+        F: FnMut(ProgressEvent),
+    {
+        if let Err(e) = KnownInstalls::record(dir, version) {
+            output_callback(ProgressEvent::Warning(format!(
+                "Failed to save the list of installs: {}",
+                e
+            )));
+        }
+    }
 
-        output_callback("Installation complete!".to_string());
-        output_callback(format!("Game installed to: {}", install_dir));
+    /// Brings the chunk cache back under its size limit after an operation
+    /// added to it, and reports what was evicted along the way.
+    fn trim_chunk_cache<F>(chunk_cache: Option<&ChunkCache>, output_callback: &mut F)
+    where
+        F: FnMut(ProgressEvent),
+    {
+        let Some(chunk_cache) = chunk_cache else {
+            return;
+        };
+        let evicted = chunk_cache.evict();
+        if evicted.files > 0 {
+            output_callback(ProgressEvent::info(format!(
+                "Chunk cache reached its limit, evicted {} chunks ({})",
+                evicted.files,
+                disk::format_size(evicted.bytes)
+            )));
+        }
+    }
 
-        Ok(0)
+    /// The installs GYeet knows about, other than the one in `skip`, for
+    /// `add_seeds`.
+    fn seed_installs(&self, skip: Option<&Path>) -> Vec<KnownInstall> {
+        let mut seeds: Vec<KnownInstall> = KnownInstalls::load().installs().cloned().collect();
+        // The install picked for patching, which GYeet may not have installed
+        if let Ok(game_dir) = Self::game_dir_for(Path::new(&self.config.votv_exe_path)) {
+            let game_dir = game_dir
+                .canonicalize()
+                .unwrap_or_else(|_| game_dir.to_path_buf());
+            let known = seeds.iter().any(|seed| seed.dir == game_dir);
+            if !known && game_dir.is_dir() && !self.config.installed_version.is_empty() {
+                seeds.push(KnownInstall {
+                    dir: game_dir,
+                    version: self.config.installed_version.clone(),
+                });
+            }
+        }
+        if let Some(skip) = skip {
+            let skip = skip.canonicalize().unwrap_or_else(|_| skip.to_path_buf());
+            seeds.retain(|seed| seed.dir != skip);
+        }
+        seeds
     }
 
-    // Helper methods
-    fn sha256_file(path: &Path) -> Result<String, String> {
-        let mut file = fs::File::open(path).map_err(|e| format!("Failed to open file: {}", e))?;
-        let mut hasher = Sha256::new();
-        let mut buffer = [0; 8192];
+    /// Lets `store` take chunks from the files of `seeds`, whatever their
+    /// version, like `desync untar --seed`. A seed is only a shortcut: every
+    /// chunk read from it is checked, and a seed that cannot be used is
+    /// skipped with a warning. Returns the bytes downloaded to read the
+    /// seeds' indexes.
+    fn add_seeds<F>(
+        &self,
+        store: &mut casync::ChunkStore,
+        seeds: &[KnownInstall],
+        chunk_cache: Option<&ChunkCache>,
+        temp_dir: &Path,
+        cancel: &CancelToken,
+        output_callback: &mut F,
+    ) -> Result<u64, PatcherError>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        if seeds.is_empty() {
+            return Ok(0);
+        }
 
-        loop {
-            let n = file
-                .read(&mut buffer)
-                .map_err(|e| format!("Failed to read file: {}", e))?;
-            if n == 0 {
-                break;
+        output_callback(ProgressEvent::info(format!(
+            "Other installs to reuse game files from: {}",
+            seeds.len()
+        )));
+        let catalog = match fetch_game_versions(&self.config, cancel) {
+            Ok(catalog) => catalog,
+            Err(e) if e.is_cancelled() => return Err(e),
+            Err(e) => {
+                output_callback(ProgressEvent::Warning(format!(
+                    "Not using other installs: {}",
+                    e
+                )));
+                return Ok(0);
+            }
+        };
+
+        let mut downloaded = 0;
+        for (n, seed) in seeds.iter().enumerate() {
+            cancel.check()?;
+            let seed_dir = temp_dir.join(format!("seed{}", n));
+            let result = catalog
+                .iter()
+                .find(|version| version.name == seed.version)
+                .ok_or_else(|| PatcherError::NotInCatalog {
+                    version: seed.version.clone(),
+                })
+                .and_then(|version| self.fetch_index(version, &seed_dir, cancel, output_callback))
+                .and_then(|caidx_path| {
+                    let index = casync::Index::load(&caidx_path)?;
+                    let seed_store = casync::ChunkStore::new(
+                        &index,
+                        &self.config.store_urls,
+                        chunk_cache,
+                        cancel,
+                    );
+                    let mut archive = casync::Archive::new(&seed_store, &caidx_path);
+                    let entries = archive.entries();
+                    downloaded += archive.downloaded();
+                    Ok(archive.seed_chunks(&entries?, &seed.dir))
+                });
+            match result {
+                Ok(chunks) => {
+                    let usable = store.add_seed(chunks);
+                    output_callback(ProgressEvent::info(format!(
+                        "Seed: {} (version {}), {} chunks usable",
+                        seed.dir.display(),
+                        seed.version,
+                        usable
+                    )));
+                }
+                Err(e) if e.is_cancelled() => return Err(e),
+                Err(e) => output_callback(ProgressEvent::Warning(format!(
+                    "Not using {} as a seed: {}",
+                    seed.dir.display(),
+                    e
+                ))),
             }
-            hasher.update(&buffer[..n]);
         }
+        Ok(downloaded)
+    }
 
-        Ok(format!("{:X}", hasher.finalize()))
+    /// Downloads the `.caidx` index of `version`, into `temp_dir` unless it
+    /// can be cached.
+    fn fetch_index<F>(
+        &self,
+        version: &GameVersion,
+        temp_dir: &Path,
+        cancel: &CancelToken,
+        output_callback: &mut F,
+    ) -> Result<PathBuf, PatcherError>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        output_callback(ProgressEvent::StageStarted(Stage::Download));
+        output_callback(ProgressEvent::info("Downloading index file..."));
+        let caidx_path = if version.hash.is_empty() {
+            // Without a hash the index cannot be verified or cached
+            let caidx_path = temp_dir.join("index.caidx");
+            Self::download_file(&version.link, &caidx_path, None, cancel, output_callback)?;
+            caidx_path
+        } else {
+            Self::cached_download(
+                &self.download_cache(),
+                &version.link,
+                &version.hash,
+                cancel,
+                output_callback,
+            )?
+        };
+
+        output_callback(ProgressEvent::info(format!(
+            "Index saved to: {}",
+            caidx_path.display()
+        )));
+        output_callback(ProgressEvent::StageFinished(Stage::Download));
+        Ok(caidx_path)
     }
 
-    fn sha256_bytes(data: &[u8]) -> String {
-        let mut hasher = Sha256::new();
-        hasher.update(data);
-        format!("{:X}", hasher.finalize())
+    /// Compares every file of the install with the index of its version and
+    /// re-fetches the missing and modified ones from the chunk store. Files
+    /// the index does not list are only reported. Returns the version.
+    pub fn run_repair<F>(
+        &self,
+        votv_exe_path: &str,
+        cancel: &CancelToken,
+        mut output_callback: F,
+    ) -> Result<String, PatcherError>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        let result = self.repair(votv_exe_path, cancel, &mut output_callback);
+        finish(result, &mut output_callback)
     }
 
-    fn fetch_patch_manifest() -> Result<PatchManifest, String> {
-        let client = reqwest::blocking::Client::new();
-        let response = client
-            .get(PATCH_MANIFEST_URL)
-            .send()
-            .map_err(|e| format!("Failed to fetch manifest: {}", e))?;
+    fn repair<F>(
+        &self,
+        votv_exe_path: &str,
+        cancel: &CancelToken,
+        output_callback: &mut F,
+    ) -> Result<String, PatcherError>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        output_callback(ProgressEvent::StageStarted(Stage::Check));
+        // VotV.exe itself may be one of the missing files
+        let game_dir = Self::game_dir_for(Path::new(votv_exe_path))?;
+        let version_name = self.detect_version(game_dir, cancel, output_callback)?;
+        output_callback(ProgressEvent::info(format!(
+            "Installed version: {}",
+            version_name
+        )));
+        output_callback(ProgressEvent::info("Fetching version catalog..."));
+        let version = fetch_game_versions(&self.config, cancel)?
+            .into_iter()
+            .find(|version| version.name == version_name)
+            .ok_or(PatcherError::NotInCatalog {
+                version: version_name,
+            })?;
+        output_callback(ProgressEvent::StageFinished(Stage::Check));
+        cancel.check()?;
+
+        let staging = StagingDir::create("repair")?;
+        let caidx_path = self.fetch_index(&version, staging.path(), cancel, output_callback)?;
+        let index = casync::Index::load(&caidx_path)?;
+        let chunk_cache = ChunkCache::new(&self.config);
+        let mut store = casync::ChunkStore::new(
+            &index,
+            &self.config.store_urls,
+            chunk_cache.as_ref(),
+            cancel,
+        );
+        store.writing_to(game_dir);
+        let result = Self::sync_with_index(
+            game_dir,
+            &caidx_path,
+            &store,
+            &HashSet::new(),
+            Stage::Repair,
+            cancel,
+            output_callback,
+        );
+        Self::trim_chunk_cache(chunk_cache.as_ref(), output_callback);
+        let synced = result?;
+        Self::record_install(game_dir, &version.name, output_callback);
+        if synced.written == 0 {
+            output_callback(ProgressEvent::success("All game files are intact!"));
+        } else {
+            output_callback(ProgressEvent::success(format!(
+                "Repaired {} files, {} bytes downloaded",
+                synced.written,
+                store.downloaded()
+            )));
+        }
 
-        let manifest: PatchManifest = response
-            .json()
-            .map_err(|e| format!("Failed to parse manifest: {}", e))?;
+        Ok(version.name)
+    }
 
-        Ok(manifest)
+    /// Switches the install in `install_dir` to `version`, older or newer,
+    /// in place: compares its files with the index of `version`, rewrites
+    /// only those that differ and deletes those only the installed version
+    /// has. Files of neither version, such as mods, are left alone. Returns
+    /// the version switched to.
+    pub fn run_switch_version<F>(
+        &self,
+        install_dir: &str,
+        version: &GameVersion,
+        cancel: &CancelToken,
+        mut output_callback: F,
+    ) -> Result<String, PatcherError>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        let result = self.switch_version(install_dir, version, cancel, &mut output_callback);
+        finish(result, &mut output_callback)
     }
 
-    fn download_file<F>(url: &str, output_callback: &mut F) -> Result<Vec<u8>, String>
+    fn switch_version<F>(
+        &self,
+        install_dir: &str,
+        version: &GameVersion,
+        cancel: &CancelToken,
+        output_callback: &mut F,
+    ) -> Result<String, PatcherError>
     where
-        F: FnMut(String),
+        F: FnMut(ProgressEvent),
     {
-        use std::io::Write;
+        output_callback(ProgressEvent::info(format!(
+            "Switching {} to version {}",
+            install_dir, version.name
+        )));
+        output_callback(ProgressEvent::StageStarted(Stage::Check));
+        let game_dir = Path::new(install_dir);
+        if !game_dir.join("VotV.exe").is_file() {
+            return Err(PatcherError::InvalidGamePath {
+                path: game_dir.to_path_buf(),
+                reason: "No VotV install in this directory",
+            });
+        }
+        // Without the installed version nothing is deleted, but the files
+        // of the new version are still put in place
+        let installed = match self.detect_version(game_dir, cancel, output_callback) {
+            Ok(installed) => Some(installed),
+            Err(e) if e.is_cancelled() => return Err(e),
+            Err(e) => {
+                output_callback(ProgressEvent::Warning(format!(
+                    "Cannot tell the installed version, files it alone has will be kept: {}",
+                    e
+                )));
+                None
+            }
+        };
+        output_callback(ProgressEvent::info("Fetching version catalog..."));
+        let catalog = fetch_game_versions(&self.config, cancel)?;
+        let installed = installed.and_then(|name| {
+            output_callback(ProgressEvent::info(format!("Installed version: {}", name)));
+            let found = catalog.iter().find(|version| version.name == name);
+            if found.is_none() {
+                output_callback(ProgressEvent::Warning(format!(
+                    "Version {} is not in the catalog, files it alone has will be kept",
+                    name
+                )));
+            }
+            found
+        });
+        output_callback(ProgressEvent::StageFinished(Stage::Check));
+        cancel.check()?;
+
+        let staging = StagingDir::create("switch")?;
+        let caidx_path = self.fetch_index(
+            version,
+            &staging.path().join("target"),
+            cancel,
+            output_callback,
+        )?;
+        let index = casync::Index::load(&caidx_path)?;
+        let chunk_cache = ChunkCache::new(&self.config);
+        if let Some(chunk_cache) = &chunk_cache {
+            output_callback(ProgressEvent::info(format!(
+                "Chunk cache: {}",
+                chunk_cache.dir().display()
+            )));
+        }
+        let mut store = casync::ChunkStore::new(
+            &index,
+            &self.config.store_urls,
+            chunk_cache.as_ref(),
+            cancel,
+        );
+
+        // The installed files are the best seed, and their paths tell which
+        // files belong to the installed version
+        let mut replaced = HashSet::new();
+        let mut index_downloads = 0;
+        if let Some(installed) = installed {
+            let installed_caidx = self.fetch_index(
+                installed,
+                &staging.path().join("installed"),
+                cancel,
+                output_callback,
+            )?;
+            let installed_index = casync::Index::load(&installed_caidx)?;
+            let installed_store = casync::ChunkStore::new(
+                &installed_index,
+                &self.config.store_urls,
+                chunk_cache.as_ref(),
+                cancel,
+            );
+            let mut archive = casync::Archive::new(&installed_store, &installed_caidx);
+            let entries = archive.entries()?;
+            index_downloads += archive.downloaded();
+            let usable = store.add_seed(archive.seed_chunks(&entries, game_dir));
+            output_callback(ProgressEvent::info(format!(
+                "Chunks already in this install: {}",
+                usable
+            )));
+            replaced = entries.into_iter().map(|entry| entry.path).collect();
+        }
+        index_downloads += self.add_seeds(
+            &mut store,
+            &self.seed_installs(Some(game_dir)),
+            chunk_cache.as_ref(),
+            staging.path(),
+            cancel,
+            output_callback,
+        )?;
+
+        store.writing_to(game_dir);
+        let result = Self::sync_with_index(
+            game_dir,
+            &caidx_path,
+            &store,
+            &replaced,
+            Stage::Switch,
+            cancel,
+            output_callback,
+        );
+        Self::trim_chunk_cache(chunk_cache.as_ref(), output_callback);
+        let synced = result?;
+        Self::record_install(game_dir, &version.name, output_callback);
+
+        output_callback(ProgressEvent::success(format!(
+            "Switched to version {}: {} files rewritten, {} removed",
+            version.name, synced.written, synced.removed
+        )));
+        output_callback(ProgressEvent::info(format!(
+            "From local files: {}, from the chunk cache: {}, downloaded: {}",
+            disk::format_size(store.seeded()),
+            disk::format_size(store.cached()),
+            disk::format_size(store.downloaded() + index_downloads)
+        )));
+        Ok(version.name.clone())
+    }
 
-        let client = reqwest::blocking::Client::new();
-        let mut response = client
-            .get(url)
-            .send()
-            .map_err(|e| format!("Failed to download: {}", e))?;
+    /// Deletes the chunks of the chunk cache that none of the installs GYeet
+    /// knows about use, like `desync prune` given their indexes. Refuses if
+    /// the index of any of them cannot be had, rather than delete chunks it
+    /// may need.
+    pub fn prune_chunk_cache<F>(
+        &self,
+        cancel: &CancelToken,
+        mut output_callback: F,
+    ) -> Result<CacheUsage, PatcherError>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        let Some(chunk_cache) = ChunkCache::new(&self.config) else {
+            return Ok(CacheUsage::default());
+        };
+        let mut versions: Vec<String> = KnownInstalls::load()
+            .installs()
+            .map(|install| install.version.clone())
+            .collect();
+        versions.sort();
+        versions.dedup();
+
+        let mut keep = HashSet::new();
+        if !versions.is_empty() {
+            output_callback(ProgressEvent::info("Fetching version catalog..."));
+            let catalog = fetch_game_versions(&self.config, cancel)?;
+            let staging = StagingDir::create("prune")?;
+            for (n, name) in versions.into_iter().enumerate() {
+                let version = catalog
+                    .iter()
+                    .find(|version| version.name == name)
+                    .ok_or(PatcherError::NotInCatalog { version: name })?;
+                let caidx_path = self.fetch_index(
+                    version,
+                    &staging.path().join(format!("index{}", n)),
+                    cancel,
+                    &mut output_callback,
+                )?;
+                keep.extend(casync::Index::load(&caidx_path)?.chunk_ids());
+            }
+        }
+        cancel.check()?;
+        chunk_cache.prune(&keep)
+    }
 
-        let total_size = response.content_length().unwrap_or(0);
-        let mut buffer = Vec::new();
-        let mut downloaded = 0u64;
-        let mut temp_buf = [0u8; 8192];
+    /// The version installed in `game_dir`: the one its pak belongs to, or
+    /// the one last recorded if the pak itself is missing or damaged.
+    fn detect_version<F>(
+        &self,
+        game_dir: &Path,
+        cancel: &CancelToken,
+        output_callback: &mut F,
+    ) -> Result<String, PatcherError>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        let pak_path = game_dir.join(PAK_RELATIVE_PATH);
+        let pak_hash = if pak_path.exists() {
+            Some(Self::pak_hash(&pak_path, false, cancel, output_callback)?)
+        } else {
+            None
+        };
+
+        output_callback(ProgressEvent::info("Fetching patch manifest..."));
+        let manifest = self.fetch_patch_manifest(cancel, output_callback)?;
+        if let Some(version) = pak_hash
+            .as_deref()
+            .and_then(|hash| manifest.version_for_hash(hash))
+        {
+            return Ok(version.to_string());
+        }
 
-        loop {
-            let n = response
-                .read(&mut temp_buf)
-                .map_err(|e| format!("Failed to read response: {}", e))?;
+        // GYeet's own record of this install, or else the version last
+        // recorded for the install picked for patching, if that is this one
+        let recorded = KnownInstalls::load()
+            .version_of(game_dir)
+            .map(str::to_string)
+            .or_else(|| {
+                let picked = Self::game_dir_for(Path::new(&self.config.votv_exe_path)).ok()?;
+                (picked == game_dir).then(|| self.config.installed_version.clone())
+            })
+            .unwrap_or_default();
+        if !recorded.is_empty() {
+            output_callback(ProgressEvent::Warning(format!(
+                "The pak file does not match any known version, assuming {} as last recorded",
+                recorded
+            )));
+            return Ok(recorded);
+        }
+        Err(match pak_hash {
+            Some(hash) => PatcherError::UnknownVersion { hash },
+            None => PatcherError::PakNotFound { path: pak_path },
+        })
+    }
 
-            if n == 0 {
-                break;
+    /// Walks the archive `caidx_path` describes, checks each entry against
+    /// `game_dir` and writes whatever is missing or differs, with chunks
+    /// from `store`, during `write_stage`. Files the archive does not list
+    /// are deleted if `replaced`, the paths of the version being replaced,
+    /// has them, and otherwise only reported.
+    fn sync_with_index<F>(
+        game_dir: &Path,
+        caidx_path: &Path,
+        store: &casync::ChunkStore,
+        replaced: &HashSet<PathBuf>,
+        write_stage: Stage,
+        cancel: &CancelToken,
+        output_callback: &mut F,
+    ) -> Result<Synced, PatcherError>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        output_callback(ProgressEvent::StageStarted(Stage::Verify));
+        output_callback(ProgressEvent::info(
+            "Comparing game files with the index...",
+        ));
+        let mut archive = casync::Archive::new(store, caidx_path);
+        let total = archive.size();
+
+        let mut listed = HashSet::new();
+        let mut broken = Vec::new();
+        let (mut files, mut missing, mut modified) = (0, 0, 0);
+        // What writing the broken files takes on top of what they replace,
+        // plus the largest one, whose old copy stays until it is renamed over
+        let (mut growth, mut largest) = (0u64, 0u64);
+        archive.walk(|archive, entry| {
+            listed.insert(entry.path.clone());
+            let (start, size) = match entry.kind {
+                EntryKind::File { offset, size } => {
+                    files += 1;
+                    (offset, size)
+                }
+                _ => (0, 0),
+            };
+
+            let local = game_dir.join(&entry.path);
+            let status = Self::check_entry(archive, entry, &local, |done| {
+                output_callback(ProgressEvent::Bytes {
+                    done: start + done,
+                    total,
+                })
+            })?;
+            match status {
+                EntryStatus::Intact => return Ok(()),
+                EntryStatus::Missing => {
+                    missing += 1;
+                    growth += size;
+                    output_callback(ProgressEvent::info(format!(
+                        "Missing: {}",
+                        entry.path.display()
+                    )));
+                }
+                EntryStatus::Modified => {
+                    modified += 1;
+                    let old_size = fs::symlink_metadata(&local).map_or(0, |m| m.len());
+                    growth += size.saturating_sub(old_size);
+                    largest = largest.max(size);
+                    output_callback(ProgressEvent::info(format!(
+                        "Modified: {}",
+                        entry.path.display()
+                    )));
+                }
+            }
+            broken.push(entry.clone());
+            Ok(())
+        })?;
+
+        let mut extra = Vec::new();
+        Self::find_extra_files(game_dir, Path::new(""), &listed, &mut extra);
+        let (obsolete, extra): (Vec<_>, Vec<_>) =
+            extra.into_iter().partition(|path| replaced.contains(path));
+        for path in extra.iter().take(MAX_LISTED_EXTRA_FILES) {
+            output_callback(ProgressEvent::info(format!(
+                "Not part of the game (left alone): {}",
+                path.display()
+            )));
+        }
+        if extra.len() > MAX_LISTED_EXTRA_FILES {
+            output_callback(ProgressEvent::info(format!(
+                "...and {} more",
+                extra.len() - MAX_LISTED_EXTRA_FILES
+            )));
+        }
+        if obsolete.is_empty() {
+            output_callback(ProgressEvent::info(format!(
+                "Checked {} files: {} missing, {} modified, {} extra",
+                files,
+                missing,
+                modified,
+                extra.len()
+            )));
+        } else {
+            output_callback(ProgressEvent::info(format!(
+                "Checked {} files: {} missing, {} modified, {} obsolete, {} extra",
+                files,
+                missing,
+                modified,
+                obsolete.len(),
+                extra.len()
+            )));
+        }
+        output_callback(ProgressEvent::StageFinished(Stage::Verify));
+
+        let mut synced = Synced::default();
+        if broken.is_empty() && obsolete.is_empty() {
+            return Ok(synced);
+        }
+        cancel.check()?;
+
+        output_callback(ProgressEvent::StageStarted(write_stage));
+        if !broken.is_empty() {
+            let mut space = SpaceCheck::new();
+            space.need(game_dir, growth + largest);
+            space.check()?;
+
+            output_callback(ProgressEvent::info(format!(
+                "Fetching {} missing or modified files...",
+                broken.len()
+            )));
+            let write_total = broken
+                .iter()
+                .map(|entry| match entry.kind {
+                    EntryKind::File { size, .. } => size,
+                    _ => 0,
+                })
+                .sum();
+            let mut meter = RateMeter::new(write_total);
+            let mut written = 0;
+            let result = archive.prefetching_files(&broken, CHUNK_DOWNLOAD_THREADS, |archive| {
+                for entry in &broken {
+                    cancel.check()?;
+                    for warning in store.take_warnings() {
+                        output_callback(ProgressEvent::Warning(warning));
+                    }
+                    let local = game_dir.join(&entry.path);
+                    Self::write_entry(archive, entry, &local, |done| {
+                        if let Some(event) = meter.update(written + done) {
+                            output_callback(event);
+                        }
+                    })?;
+                    if let EntryKind::File { size, .. } = entry.kind {
+                        written += size;
+                    }
+                }
+                Ok(())
+            });
+            for warning in store.take_warnings() {
+                output_callback(ProgressEvent::Warning(warning));
             }
+            result?;
+            synced.written = broken.len();
+        }
 
-            buffer
-                .write_all(&temp_buf[..n])
-                .map_err(|e| format!("Failed to write to buffer: {}", e))?;
+        // Written first: the old files may still serve as seeds until then
+        for path in &obsolete {
+            cancel.check()?;
+            synced.removed += Self::remove_obsolete(game_dir, path, replaced)?;
+            if game_dir.join(path).exists() {
+                output_callback(ProgressEvent::Warning(format!(
+                    "Kept {}: it holds files that are not part of the game",
+                    path.display()
+                )));
+            } else {
+                output_callback(ProgressEvent::info(format!("Removed: {}", path.display())));
+            }
+        }
+        output_callback(ProgressEvent::StageFinished(write_stage));
+        Ok(synced)
+    }
 
-            downloaded += n as u64;
+    /// Deletes `path` under `game_dir`. Inside a directory only what
+    /// `replaced` lists is deleted, and the directory itself if that leaves
+    /// it empty. Returns the number of paths deleted.
+    fn remove_obsolete(
+        game_dir: &Path,
+        path: &Path,
+        replaced: &HashSet<PathBuf>,
+    ) -> Result<usize, PatcherError> {
+        let local = game_dir.join(path);
+        let Ok(metadata) = fs::symlink_metadata(&local) else {
+            return Ok(0);
+        };
+        if !metadata.is_dir() {
+            fs::remove_file(&local).map_err(PatcherError::io("Failed to delete", &local))?;
+            return Ok(1);
+        }
 
-            // Report progress every 512KB
-            if downloaded % (512 * 1024) == 0 || downloaded == total_size {
-                if total_size > 0 {
-                    let _percent = (downloaded as f64 / total_size as f64) * 100.0;
-                    output_callback(format!("PROGRESS:{}:{}", downloaded, total_size));
-                }
+        let mut removed = 0;
+        let items = fs::read_dir(&local).map_err(PatcherError::io("Failed to read", &local))?;
+        for item in items.flatten() {
+            let child = path.join(item.file_name());
+            if replaced.contains(&child) {
+                removed += Self::remove_obsolete(game_dir, &child, replaced)?;
             }
         }
+        if fs::remove_dir(&local).is_ok() {
+            removed += 1;
+        }
+        Ok(removed)
+    }
 
-        if total_size > 0 {
-            output_callback(format!(
-                "Downloaded: {} / {} bytes ({:.1}%)",
-                downloaded,
-                total_size,
-                (downloaded as f64 / total_size as f64) * 100.0
-            ));
+    fn check_entry<P>(
+        archive: &mut casync::Archive,
+        entry: &casync::Entry,
+        local: &Path,
+        progress: P,
+    ) -> Result<EntryStatus, PatcherError>
+    where
+        P: FnMut(u64),
+    {
+        let Ok(metadata) = fs::symlink_metadata(local) else {
+            return Ok(EntryStatus::Missing);
+        };
+        let intact = match &entry.kind {
+            EntryKind::Directory => metadata.is_dir(),
+            EntryKind::File { offset, size } => {
+                metadata.is_file() && archive.file_matches(*offset, *size, local, progress)?
+            }
+            EntryKind::Symlink { target } => {
+                fs::read_link(local).is_ok_and(|found| found == *target)
+            }
+            EntryKind::Other => true,
+        };
+        Ok(if intact {
+            EntryStatus::Intact
         } else {
-            output_callback(format!("Downloaded: {} bytes", downloaded));
+            EntryStatus::Modified
+        })
+    }
+
+    /// Puts `entry` at `local` the way the archive has it, replacing
+    /// whatever is there.
+    fn write_entry<P>(
+        archive: &mut casync::Archive,
+        entry: &casync::Entry,
+        local: &Path,
+        progress: P,
+    ) -> Result<(), PatcherError>
+    where
+        P: FnMut(u64),
+    {
+        match &entry.kind {
+            EntryKind::Directory => {
+                if local.is_file() || local.is_symlink() {
+                    fs::remove_file(local).map_err(PatcherError::io("Failed to delete", local))?;
+                }
+                fs::create_dir_all(local)
+                    .map_err(PatcherError::io("Failed to create directory", local))
+            }
+            EntryKind::File { offset, size } => {
+                // Written next to the original and renamed over it, so an
+                // interrupted write never leaves a half-written file behind
+                let mut temp_name = local.as_os_str().to_os_string();
+                temp_name.push(".gyeet_part");
+                let temp_path = PathBuf::from(temp_name);
+                let result = archive
+                    .extract_file(*offset, *size, &temp_path, progress)
+                    .and_then(|()| {
+                        fs::rename(&temp_path, local)
+                            .map_err(PatcherError::io("Failed to replace", local))
+                    });
+                if result.is_err() {
+                    let _ = fs::remove_file(&temp_path);
+                }
+                result
+            }
+            EntryKind::Symlink { target } => {
+                let _ = fs::remove_file(local);
+                std::os::unix::fs::symlink(target, local)
+                    .map_err(PatcherError::io("Failed to create link", local))
+            }
+            EntryKind::Other => Ok(()),
         }
+    }
 
-        Ok(buffer)
+    /// Collects the paths under `game_dir/dir` that are not in `listed`.
+    /// A directory that is not listed is reported once, not file by file.
+    fn find_extra_files(
+        game_dir: &Path,
+        dir: &Path,
+        listed: &HashSet<PathBuf>,
+        extra: &mut Vec<PathBuf>,
+    ) {
+        let Ok(items) = fs::read_dir(game_dir.join(dir)) else {
+            return;
+        };
+        let mut items: Vec<_> = items.flatten().collect();
+        items.sort_by_key(|item| item.file_name());
+        for item in items {
+            let path = dir.join(item.file_name());
+            if path.as_os_str() == backup::SNAPSHOT_DIR_NAME {
+                continue;
+            }
+            if !listed.contains(&path) {
+                extra.push(path);
+            } else if item.file_type().is_ok_and(|t| t.is_dir()) {
+                Self::find_extra_files(game_dir, &path, listed, extra);
+            }
+        }
+    }
+
+    // Helper methods
+    /// The directory holding VotV.exe, after checking that is what was picked.
+    fn game_dir_for(exe_path: &Path) -> Result<&Path, PatcherError> {
+        if exe_path.file_name().and_then(|n| n.to_str()) != Some("VotV.exe") {
+            return Err(PatcherError::InvalidGamePath {
+                path: exe_path.to_path_buf(),
+                reason: "Selected file must be VotV.exe",
+            });
+        }
+        exe_path.parent().ok_or(PatcherError::InvalidGamePath {
+            path: exe_path.to_path_buf(),
+            reason: "Invalid exe path",
+        })
     }
 
-    fn extract_7z<F>(data: &[u8], target_dir: &Path, output_callback: &mut F) -> Result<(), String>
+    /// The pak's SHA256, taken from the hash cache when the pak is unchanged
+    /// since it was last hashed.
+    fn pak_hash<F>(
+        pak_path: &Path,
+        force_rehash: bool,
+        cancel: &CancelToken,
+        output_callback: &mut F,
+    ) -> Result<String, PatcherError>
     where
-        F: FnMut(String),
+        F: FnMut(ProgressEvent),
     {
-        // Write to temp file first
-        let temp_file = target_dir.join(".gyeet_temp_patch.7z");
-        fs::write(&temp_file, data).map_err(|e| format!("Failed to write temp file: {}", e))?;
+        if !force_rehash {
+            if let Some(hash) = HashCache::load().get(pak_path) {
+                output_callback(ProgressEvent::info(format!(
+                    "Pak file unchanged since last check: {}",
+                    pak_path.display()
+                )));
+                return Ok(hash.to_string());
+            }
+        }
 
-        // Use 7z command to extract
-        let output = Command::new("7z")
-            .arg("x")
-            .arg("-y")
-            .arg(&temp_file)
-            .arg(format!("-o{}", target_dir.display()))
-            .output()
-            .map_err(|e| {
-                format!(
-                    "Failed to run 7z: {}. Make sure p7zip-full is installed.",
+        output_callback(ProgressEvent::info(format!(
+            "Hashing pak file: {}",
+            pak_path.display()
+        )));
+        Self::hash_and_remember(pak_path, cancel, output_callback)
+    }
+
+    /// Hashes `path` and stores the result in the hash cache for next time.
+    fn hash_and_remember<F>(
+        path: &Path,
+        cancel: &CancelToken,
+        output_callback: &mut F,
+    ) -> Result<String, PatcherError>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        // Stamp before reading, so a change made while hashing is noticed
+        let stamp = FileStamp::read(path);
+        let hash = Self::sha256_file(path, cancel, output_callback)?;
+        if let Some(stamp) = stamp {
+            if let Err(e) = HashCache::load().insert(path, stamp, &hash) {
+                output_callback(ProgressEvent::Warning(format!(
+                    "Failed to remember the hash of {}: {}",
+                    path.display(),
                     e
-                )
-            })?;
+                )));
+            }
+        }
+        Ok(hash)
+    }
 
-        // Clean up temp file
-        let _ = fs::remove_file(&temp_file);
+    fn sha256_file<F>(
+        path: &Path,
+        cancel: &CancelToken,
+        output_callback: &mut F,
+    ) -> Result<String, PatcherError>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        let mut file = fs::File::open(path).map_err(PatcherError::io("Failed to open", path))?;
+        let total = file.metadata().map(|m| m.len()).unwrap_or(0);
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0; HASH_BUFFER_SIZE];
+        let mut done = 0;
 
-        if !output.status.success() {
-            return Err(format!(
-                "7z extraction failed: {}",
-                String::from_utf8_lossy(&output.stderr)
-            ));
+        loop {
+            cancel.check()?;
+            let n = file
+                .read(&mut buffer)
+                .map_err(PatcherError::io("Failed to read", path))?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buffer[..n]);
+            done += n as u64;
+            output_callback(ProgressEvent::Bytes { done, total });
         }
 
-        output_callback("Extraction complete".to_string());
-        Ok(())
+        Ok(format!("{:X}", hasher.finalize()))
     }
 
-    fn find_patch_script(search_dir: &Path) -> Result<PathBuf, String> {
-        // Search for apply_patch.sh in the directory and subdirectories
-        for entry in
-            fs::read_dir(search_dir).map_err(|e| format!("Failed to read directory: {}", e))?
-        {
-            let entry = entry.map_err(|e| format!("Failed to read entry: {}", e))?;
-            let path = entry.path();
-
-            if path.is_dir() {
-                // Check in subdirectory
-                let script_path = path.join("apply_patch.sh");
-                if script_path.exists() {
-                    return Ok(script_path);
-                }
-            } else if path.file_name().and_then(|n| n.to_str()) == Some("apply_patch.sh") {
-                return Ok(path);
-            }
+    fn fetch_patch_manifest<F>(
+        &self,
+        cancel: &CancelToken,
+        output_callback: &mut F,
+    ) -> Result<PatchManifest, PatcherError>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        if self.config.allow_unsigned_manifests {
+            output_callback(ProgressEvent::Warning(
+                "Manifest signature checks are disabled".to_string(),
+            ));
+        }
+        let (body, signature_text) =
+            http::with_mirrors(&self.config.patch_manifest_urls, "patch manifest", |url| {
+                fetch_signed(&self.config, url, "patch manifest", cancel)
+            })?;
+
+        let manifest = parse_manifest(&body, "patch manifest")?;
+
+        // Keep the verified copy around for offline patching, with its
+        // signature so it is checked again when loaded
+        let cache_path = Self::manifest_cache_path();
+        let mut signature_path = cache_path.as_os_str().to_os_string();
+        signature_path.push(".minisig");
+        let saved = cache_path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| match &signature_text {
+                Some(signature_text) => fs::write(&signature_path, signature_text),
+                None => match fs::remove_file(&signature_path) {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+                    _ => Ok(()),
+                },
+            })
+            .and_then(|()| fs::write(&cache_path, &body));
+        if let Err(e) = saved {
+            output_callback(ProgressEvent::Warning(format!(
+                "Failed to keep a copy of the patch manifest for offline use: {}",
+                e
+            )));
         }
 
-        Err("apply_patch.sh not found after extraction".to_string())
+        Ok(manifest)
     }
 
-    fn run_patch_script<F>(script_path: &Path, output_callback: &mut F) -> Result<(), String>
+    fn manifest_cache_path() -> PathBuf {
+        dirs::cache_dir()
+            .unwrap_or_else(std::env::temp_dir)
+            .join("gyeet")
+            .join("patch_manifest.json")
+    }
+
+    /// The last downloaded patch manifest, checked against the signature
+    /// kept with it like any manifest file.
+    fn load_cached_manifest<F>(
+        &self,
+        output_callback: &mut F,
+    ) -> Result<PatchManifest, PatcherError>
     where
-        F: FnMut(String),
+        F: FnMut(ProgressEvent),
     {
-        let script_dir = script_path.parent().ok_or("Invalid script path")?;
+        let cache_path = Self::manifest_cache_path();
+        if !cache_path.is_file() {
+            return Err(PatcherError::NoCachedManifest);
+        }
+        self.load_local_manifest(&cache_path, output_callback)
+    }
 
-        // Make scripts executable
-        let _ = Command::new("chmod")
-            .arg("+x")
-            .arg(script_path)
-            .arg(script_dir.join("hpatchz"))
-            .output();
+    /// Reads a manifest file, holding it to the same signature rules as a
+    /// downloaded one. The signature is expected next to it as `.minisig`.
+    fn load_local_manifest<F>(
+        &self,
+        path: &Path,
+        output_callback: &mut F,
+    ) -> Result<PatchManifest, PatcherError>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        let body = fs::read(path).map_err(PatcherError::io("Failed to read manifest", path))?;
 
-        // Run the patch script
-        let mut child = Command::new("bash")
-            .arg(script_path)
-            .current_dir(script_dir)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-            .map_err(|e| format!("Failed to run patch script: {}", e))?;
+        if self.config.allow_unsigned_manifests {
+            output_callback(ProgressEvent::Warning(
+                "Manifest signature checks are disabled".to_string(),
+            ));
+        } else {
+            let mut signature_path = path.as_os_str().to_os_string();
+            signature_path.push(".minisig");
+            let signature_path = PathBuf::from(signature_path);
+            let signature_text = fs::read_to_string(&signature_path).map_err(PatcherError::io(
+                "Failed to read manifest signature",
+                &signature_path,
+            ))?;
+            check_signature(&self.config, &body, &signature_text, "patch manifest")?;
+        }
 
-        if let Some(stdout) = child.stdout.take() {
-            let reader = BufReader::new(stdout);
-            for line in reader.lines() {
-                if let Ok(line) = line {
-                    output_callback(format!("   {}", line));
-                }
+        parse_manifest(&body, "patch manifest")
+    }
+
+    /// Downloads (unless it is already local), extracts and applies a single
+    /// patch to the pak.
+    fn apply_patch<F>(
+        planned: &PlannedPatch,
+        pak_path: &Path,
+        cache: &DownloadCache,
+        cancel: &CancelToken,
+        output_callback: &mut F,
+    ) -> Result<(), PatcherError>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        let patch_info = &planned.hop.patch;
+        let patch_path = match &planned.local_archive {
+            Some(archive) => {
+                output_callback(ProgressEvent::info(format!(
+                    "Using patch file: {}",
+                    archive.display()
+                )));
+                archive.clone()
+            }
+            None => {
+                output_callback(ProgressEvent::StageStarted(Stage::Download));
+                output_callback(ProgressEvent::info(format!(
+                    "Patch URL: {}",
+                    patch_info.url
+                )));
+                output_callback(ProgressEvent::info(format!(
+                    "Expected SHA256: {}",
+                    patch_info.sha256
+                )));
+
+                let patch_path = Self::cached_download(
+                    cache,
+                    &patch_info.url,
+                    &patch_info.sha256,
+                    cancel,
+                    output_callback,
+                )?;
+                output_callback(ProgressEvent::success("Patch verified successfully"));
+                output_callback(ProgressEvent::StageFinished(Stage::Download));
+                patch_path
             }
+        };
+
+        // Extract patch. The staging directory is removed when it goes out
+        // of scope, whether the patch applied, failed or was cancelled.
+        let staging = StagingDir::create("patch")?;
+        output_callback(ProgressEvent::StageStarted(Stage::Extract));
+        output_callback(ProgressEvent::info(format!(
+            "Extracting patch to: {}",
+            staging.path().display()
+        )));
+
+        let extracted = Self::extract_7z(&patch_path, staging.path(), cancel, output_callback)?;
+        output_callback(ProgressEvent::StageFinished(Stage::Extract));
+
+        output_callback(ProgressEvent::StageStarted(Stage::Patch));
+        output_callback(ProgressEvent::info("Applying patch..."));
+        Self::apply_patch_payload(&patch_path, &extracted, pak_path, cancel, output_callback)?;
+
+        output_callback(ProgressEvent::info("Cleaning up temporary files..."));
+        drop(staging);
+        output_callback(ProgressEvent::StageFinished(Stage::Patch));
+        Ok(())
+    }
+
+    /// Refuses an update that would run out of disk space halfway. Counts
+    /// the patch downloads, their extraction, the pak written next to the
+    /// current one and the backup of the current one.
+    fn check_update_space<F>(
+        report: &UpdateReport,
+        cache: &DownloadCache,
+        output_callback: &mut F,
+    ) -> Result<(), PatcherError>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        output_callback(ProgressEvent::info("Checking free disk space..."));
+        let mut space = SpaceCheck::new();
+        let mut largest_patch = 0;
+        for planned in &report.route {
+            let size = match &planned.local_archive {
+                Some(archive) => fs::metadata(archive).map(|m| m.len()).unwrap_or(0),
+                None => {
+                    let size = planned.size.unwrap_or(0);
+                    if !cache.contains(&planned.hop.patch.sha256) {
+                        space.need(cache.dir(), size);
+                    }
+                    size
+                }
+            };
+            largest_patch = largest_patch.max(size);
         }
 
-        let status = child
-            .wait()
-            .map_err(|e| format!("Failed to wait for patch script: {}", e))?;
+        // Patches are extracted and applied one at a time
+        let pak_size = fs::metadata(&report.pak_path).map(|m| m.len()).unwrap_or(0);
+        space.need(&StagingDir::root(), largest_patch * EXTRACTED_PATCH_FACTOR);
+        space.need(&report.pak_path, pak_size + largest_patch);
+        // The backup shares the pak's filesystem, unless that is read-only
+        space.need(&Snapshot::dir_for(&report.game_dir), pak_size);
+        space.check()
+    }
 
-        if !status.success() {
-            return Err(format!(
-                "Patch script failed with code: {}",
-                status.code().unwrap_or(-1)
+    /// Asks the server for the size of `url` without downloading it.
+    fn remote_size(url: &str) -> Option<u64> {
+        let response = http::client().head(url).send().ok()?;
+        if !response.status().is_success() {
+            return None;
+        }
+        response
+            .headers()
+            .get(reqwest::header::CONTENT_LENGTH)?
+            .to_str()
+            .ok()?
+            .parse()
+            .ok()
+    }
+
+    fn download_cache(&self) -> DownloadCache {
+        DownloadCache::new(&self.config)
+    }
+
+    /// The file with `sha256` from the download cache, downloaded from `url`
+    /// first unless a cached copy still matches the hash.
+    fn cached_download<F>(
+        cache: &DownloadCache,
+        url: &str,
+        sha256: &str,
+        cancel: &CancelToken,
+        output_callback: &mut F,
+    ) -> Result<PathBuf, PatcherError>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        if let Some(path) = cache.lookup(sha256) {
+            if Self::sha256_file(&path, cancel, output_callback)?.eq_ignore_ascii_case(sha256) {
+                output_callback(ProgressEvent::info(format!(
+                    "Using cached download: {}",
+                    path.display()
+                )));
+                return Ok(path);
+            }
+            output_callback(ProgressEvent::Warning(
+                "Cached copy is damaged, downloading it again".to_string(),
             ));
+            cache.remove(sha256);
         }
 
-        Ok(())
+        // Verified against the expected hash while streaming
+        output_callback(ProgressEvent::info(format!("Downloading from: {}", url)));
+        let path = cache.path_for(sha256);
+        Self::download_file(url, &path, Some(sha256), cancel, output_callback)?;
+
+        let evicted = cache.evict(sha256);
+        if evicted.files > 0 {
+            output_callback(ProgressEvent::info(format!(
+                "Removed {} old download(s) ({} bytes) from the cache",
+                evicted.files, evicted.bytes
+            )));
+        }
+        Ok(path)
     }
 
-    fn ensure_desync<F>(output_callback: &mut F) -> Result<PathBuf, String>
+    /// Streams `url` into `dest`, hashing as it goes. Data lands in
+    /// `dest.part` first, which is resumed with a Range request if a previous
+    /// attempt was interrupted, and is only renamed to `dest` once the
+    /// SHA256 matches `expected_sha256` (when given). Returns the SHA256.
+    fn download_file<F>(
+        url: &str,
+        dest: &Path,
+        expected_sha256: Option<&str>,
+        cancel: &CancelToken,
+        output_callback: &mut F,
+    ) -> Result<String, PatcherError>
     where
-        F: FnMut(String),
+        F: FnMut(ProgressEvent),
     {
-        // Check if desync exists in the current directory or PATH
-        let local_desync = PathBuf::from("./desync");
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent).map_err(PatcherError::io(
+                "Failed to create download directory",
+                parent,
+            ))?;
+        }
+
+        let mut part_name = dest.as_os_str().to_os_string();
+        part_name.push(".part");
+        let part_path = PathBuf::from(part_name);
+
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(&part_path)
+            .map_err(PatcherError::io("Failed to open", &part_path))?;
 
-        if local_desync.exists() && local_desync.is_file() {
-            return Ok(local_desync);
+        // Re-hash whatever an earlier run already downloaded
+        let mut hasher = Sha256::new();
+        let mut downloaded = std::io::copy(&mut file, &mut HashWriter(&mut hasher))
+            .map_err(PatcherError::io("Failed to read", &part_path))?;
+        if downloaded > 0 {
+            output_callback(ProgressEvent::info(format!(
+                "Resuming download at {} bytes",
+                downloaded
+            )));
         }
 
-        // Check in PATH
-        if let Ok(output) = Command::new("which").arg("desync").output() {
-            if output.status.success() {
-                let path_str = String::from_utf8_lossy(&output.stdout).trim().to_string();
-                if !path_str.is_empty() {
-                    return Ok(PathBuf::from(path_str));
+        let mut attempt = 1;
+        let result = loop {
+            match Self::download_attempt(
+                url,
+                &part_path,
+                &mut file,
+                &mut hasher,
+                &mut downloaded,
+                cancel,
+                output_callback,
+            ) {
+                Ok(()) => break Ok(()),
+                Err(DownloadError::Interrupted(e)) if attempt < MAX_DOWNLOAD_ATTEMPTS => {
+                    attempt += 1;
+                    output_callback(ProgressEvent::Warning(format!(
+                        "Download interrupted: {}. Resuming at {} bytes (attempt {}/{})",
+                        e, downloaded, attempt, MAX_DOWNLOAD_ATTEMPTS
+                    )));
+                    if let Err(e) = cancel.sleep(http::backoff(attempt - 1)) {
+                        break Err(e);
+                    }
                 }
+                Err(DownloadError::Interrupted(e)) | Err(DownloadError::Fatal(e)) => {
+                    break Err(e);
+                }
+            }
+        };
+        drop(file);
+        if let Err(e) = result {
+            // A cancelled download is not worth resuming
+            if e.is_cancelled() {
+                let _ = fs::remove_file(&part_path);
             }
+            return Err(e);
         }
 
-        // Download desync
-        output_callback("Downloading desync binary...".to_string());
-        output_callback(format!("From: {}", DESYNC_URL));
+        let hash = format!("{:X}", hasher.finalize());
+        if let Some(expected) = expected_sha256 {
+            if !hash.eq_ignore_ascii_case(expected) {
+                let _ = fs::remove_file(&part_path);
+                return Err(PatcherError::HashMismatch {
+                    subject: url.to_string(),
+                    expected: expected.to_string(),
+                    actual: hash,
+                });
+            }
+        }
 
-        let client = reqwest::blocking::Client::new();
-        let mut response = client
-            .get(DESYNC_URL)
-            .send()
-            .map_err(|e| format!("Failed to download desync: {}", e))?;
+        fs::rename(&part_path, dest)
+            .map_err(PatcherError::io("Failed to move download into place", dest))?;
+        Ok(hash)
+    }
 
-        let mut tar_gz_data = Vec::new();
-        response
-            .read_to_end(&mut tar_gz_data)
-            .map_err(|e| format!("Failed to read desync archive: {}", e))?;
-
-        output_callback(format!("Downloaded {} bytes", tar_gz_data.len()));
-
-        // Extract to temp directory
-        let temp_dir = std::env::temp_dir().join(format!("gyeet_desync_{}", std::process::id()));
-        fs::create_dir_all(&temp_dir)
-            .map_err(|e| format!("Failed to create temp directory: {}", e))?;
-
-        let tar_gz_path = temp_dir.join("desync.tar.gz");
-        fs::write(&tar_gz_path, &tar_gz_data)
-            .map_err(|e| format!("Failed to write tar.gz: {}", e))?;
-
-        output_callback("Extracting desync...".to_string());
-
-        // Extract using tar command
-        let output = Command::new("tar")
-            .arg("-xzf")
-            .arg(&tar_gz_path)
-            .arg("-C")
-            .arg(&temp_dir)
-            .output()
-            .map_err(|e| format!("Failed to run tar: {}", e))?;
-
-        if !output.status.success() {
-            return Err(format!(
-                "tar extraction failed: {}",
-                String::from_utf8_lossy(&output.stderr)
-            ));
+    fn download_attempt<F>(
+        url: &str,
+        part_path: &Path,
+        file: &mut fs::File,
+        hasher: &mut Sha256,
+        downloaded: &mut u64,
+        cancel: &CancelToken,
+        output_callback: &mut F,
+    ) -> Result<(), DownloadError>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        use std::io::{Seek, SeekFrom, Write};
+
+        let interrupted = |e: &dyn std::fmt::Display| {
+            DownloadError::Interrupted(PatcherError::Network {
+                url: url.to_string(),
+                message: e.to_string(),
+            })
+        };
+        let file_error = |e: std::io::Error| {
+            DownloadError::Fatal(PatcherError::io("Failed to write", part_path)(e))
+        };
+
+        // Drop anything written but not hashed by a failed attempt
+        file.set_len(*downloaded).map_err(file_error)?;
+        file.seek(SeekFrom::Start(*downloaded))
+            .map_err(file_error)?;
+
+        let mut request = http::client().get(url);
+        if *downloaded > 0 {
+            request = request.header(reqwest::header::RANGE, format!("bytes={}-", downloaded));
         }
+        let mut response = request.send().map_err(|e| {
+            if http::is_transient(&e) {
+                interrupted(&e)
+            } else {
+                DownloadError::Fatal(PatcherError::network(url)(e))
+            }
+        })?;
 
-        // Find the desync binary
-        let desync_binary = Self::find_file_recursive(&temp_dir, "desync")?;
+        let status = response.status();
+        if status == reqwest::StatusCode::RANGE_NOT_SATISFIABLE && *downloaded > 0 {
+            // The partial file already holds the whole resource
+            return Ok(());
+        }
+        let status_error = PatcherError::HttpStatus {
+            url: url.to_string(),
+            status: status.as_u16(),
+        };
+        if http::is_transient_status(status) {
+            return Err(DownloadError::Interrupted(status_error));
+        }
+        if !status.is_success() {
+            return Err(DownloadError::Fatal(status_error));
+        }
 
-        // Move to current directory
-        let target_path = PathBuf::from("./desync");
-        fs::copy(&desync_binary, &target_path)
-            .map_err(|e| format!("Failed to copy desync binary: {}", e))?;
+        if *downloaded > 0 && status != reqwest::StatusCode::PARTIAL_CONTENT {
+            output_callback(ProgressEvent::Warning(
+                "Server does not support resuming, restarting download".to_string(),
+            ));
+            *hasher = Sha256::new();
+            *downloaded = 0;
+            file.set_len(0).map_err(file_error)?;
+            file.seek(SeekFrom::Start(0)).map_err(file_error)?;
+        }
 
-        // Make executable
-        let _ = Command::new("chmod").arg("+x").arg(&target_path).output();
+        let total_size = response
+            .content_length()
+            .map(|len| len + *downloaded)
+            .unwrap_or(0);
+        let mut buffer = vec![0u8; 64 * 1024];
+        let mut last_reported = *downloaded;
 
-        // Cleanup
-        let _ = fs::remove_dir_all(&temp_dir);
+        loop {
+            cancel.check().map_err(DownloadError::Fatal)?;
+            let n = response.read(&mut buffer).map_err(|e| interrupted(&e))?;
+            if n == 0 {
+                break;
+            }
 
-        output_callback(format!("desync installed to: {}", target_path.display()));
+            file.write_all(&buffer[..n]).map_err(file_error)?;
+            hasher.update(&buffer[..n]);
+            *downloaded += n as u64;
 
-        Ok(target_path)
-    }
+            // Report progress every 512KB
+            if total_size > 0 && *downloaded - last_reported >= 512 * 1024 {
+                last_reported = *downloaded;
+                output_callback(ProgressEvent::Bytes {
+                    done: *downloaded,
+                    total: total_size,
+                });
+            }
+        }
 
-    fn find_file_recursive(dir: &Path, filename: &str) -> Result<PathBuf, String> {
-        for entry in fs::read_dir(dir).map_err(|e| format!("Failed to read directory: {}", e))? {
-            let entry = entry.map_err(|e| format!("Failed to read entry: {}", e))?;
-            let path = entry.path();
+        if total_size > 0 && *downloaded < total_size {
+            return Err(interrupted(&format!(
+                "connection closed at {} of {} bytes",
+                downloaded, total_size
+            )));
+        }
 
-            if path.is_file() && path.file_name().and_then(|n| n.to_str()) == Some(filename) {
-                return Ok(path);
-            } else if path.is_dir() {
-                if let Ok(found) = Self::find_file_recursive(&path, filename) {
-                    return Ok(found);
-                }
-            }
+        if total_size > 0 {
+            output_callback(ProgressEvent::info(format!(
+                "Downloaded: {} / {} bytes ({:.1}%)",
+                downloaded,
+                total_size,
+                (*downloaded as f64 / total_size as f64) * 100.0
+            )));
+        } else {
+            output_callback(ProgressEvent::info(format!(
+                "Downloaded: {} bytes",
+                downloaded
+            )));
         }
 
-        Err(format!(
-            "File '{}' not found in {}",
-            filename,
-            dir.display()
-        ))
+        Ok(())
     }
 
-    fn run_desync<F>(
-        desync_bin: &Path,
-        caidx_path: &Path,
-        install_dir: &str,
+    fn extract_7z<F>(
+        archive_path: &Path,
+        target_dir: &Path,
+        cancel: &CancelToken,
         output_callback: &mut F,
-    ) -> Result<(), String>
+    ) -> Result<Vec<PathBuf>, PatcherError>
     where
-        F: FnMut(String),
+        F: FnMut(ProgressEvent),
     {
-        output_callback(format!(
-            "🔧 Running: {} untar -i -s {} {} {}",
-            desync_bin.display(),
-            STORE_URL,
-            caidx_path.display(),
-            install_dir
-        ));
-
-        eprintln!("[DEBUG] Starting desync process...");
-        output_callback("Starting unpacking process...".to_string());
-
-        let mut child = Command::new(desync_bin)
-            .arg("untar")
-            .arg("--verbose")
-            .arg("--no-same-owner")
-            .arg("-i")
-            .arg("-s")
-            .arg(STORE_URL)
-            .arg(caidx_path)
-            .arg(install_dir)
-            .arg("-n")
-            .arg("16")
-            .stderr(Stdio::piped())
-            .spawn()
-            .map_err(|e| format!("Failed to run desync: {}", e))?;
-
-        eprintln!("[DEBUG] desync spawned successfully");
-        
-        // Monitor stderr for activity and send periodic updates
-        let stderr = child.stderr.take().ok_or("Failed to capture stderr")?;
-        let running = Arc::new(Mutex::new(true));
-        let running_clone = Arc::clone(&running);
-        
-        // Thread to read stderr
-        let stderr_handle = thread::spawn(move || {
-            eprintln!("[DEBUG] stderr reader thread started");
-            let reader = BufReader::new(stderr);
-            let mut line_count = 0;
-            
-            for line in reader.lines() {
-                if let Ok(line) = line {
-                    line_count += 1;
-                    
-                    // Print every line to terminal for debugging
-                    eprintln!("[STDERR {}] {}", line_count, line);
-                    
-                    // Look for progress indicator
-                    if line.contains("Unpacking") || line.contains("%") {
-                        eprintln!("[DEBUG] Found potential progress line!");
+        let archive_error = |e: sevenz_rust::Error| PatcherError::Archive {
+            path: archive_path.to_path_buf(),
+            message: e.to_string(),
+        };
+        let mut reader =
+            sevenz_rust::SevenZReader::open(archive_path, sevenz_rust::Password::empty())
+                .map_err(archive_error)?;
+
+        let entry_count = reader
+            .archive()
+            .files
+            .iter()
+            .filter(|entry| !entry.is_anti_item())
+            .count();
+        let total_size: u64 = reader
+            .archive()
+            .files
+            .iter()
+            .filter(|entry| !entry.is_anti_item() && !entry.is_directory())
+            .map(|entry| entry.size())
+            .sum();
+        let mut extracted: Vec<PathBuf> = Vec::new();
+        let mut index = 0;
+        // Patch archives are mostly one large entry, so the copy checks for
+        // cancellation and reports progress as it goes
+        let mut buffer = vec![0u8; 1024 * 1024];
+        let mut written = 0u64;
+        let mut meter = RateMeter::new(total_size);
+
+        let result = reader.for_each_entries(|entry, entry_reader| {
+            if entry.is_anti_item() {
+                return Ok(true);
+            }
+            if cancel.is_cancelled() {
+                return Err(sevenz_rust::Error::other("Cancelled"));
+            }
+            index += 1;
+
+            let relative =
+                Self::sanitize_entry_path(entry.name()).map_err(sevenz_rust::Error::other)?;
+            let dest = target_dir.join(&relative);
+            output_callback(ProgressEvent::info(format!(
+                "[{}/{}] {} ({} bytes)",
+                index,
+                entry_count,
+                relative.display(),
+                entry.size()
+            )));
+
+            let parent = if entry.is_directory() {
+                dest.as_path()
+            } else {
+                dest.parent().unwrap_or(target_dir)
+            };
+            fs::create_dir_all(parent).map_err(sevenz_rust::Error::io)?;
+
+            if !entry.is_directory() {
+                use std::io::Write;
+
+                let mut file = fs::File::create(&dest).map_err(sevenz_rust::Error::io)?;
+                extracted.push(dest);
+                loop {
+                    if cancel.is_cancelled() {
+                        return Err(sevenz_rust::Error::other("Cancelled"));
+                    }
+                    let n = match entry_reader.read(&mut buffer) {
+                        Ok(0) => break,
+                        Ok(n) => n,
+                        Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                        Err(e) => return Err(sevenz_rust::Error::io(e)),
+                    };
+                    file.write_all(&buffer[..n])
+                        .map_err(sevenz_rust::Error::io)?;
+                    written += n as u64;
+                    if let Some(event) = meter.update(written) {
+                        output_callback(event);
                     }
                 }
             }
-            
-            *running_clone.lock().unwrap() = false;
-            eprintln!("[DEBUG] stderr reader finished, read {} lines", line_count);
+            Ok(true)
         });
 
-        // Send periodic status updates to GUI
-        let mut update_count = 0;
-        while *running.lock().unwrap() {
-            thread::sleep(std::time::Duration::from_secs(2));
-            if *running.lock().unwrap() {
-                update_count += 1;
-                output_callback(format!("Unpacking in progress... ({} seconds)", update_count * 2));
-                eprintln!("[DEBUG] Sent update #{}", update_count);
-            }
+        if let Err(e) = result {
+            cancel.check()?;
+            return Err(archive_error(e));
         }
 
-        eprintln!("[DEBUG] Waiting for stderr thread...");
-        let _ = stderr_handle.join();
+        output_callback(ProgressEvent::info(format!(
+            "Extraction complete ({} entries)",
+            entry_count
+        )));
+        Ok(extracted)
+    }
 
-        eprintln!("[DEBUG] Waiting for process to exit...");
-        let status = child
-            .wait()
-            .map_err(|e| format!("Failed to wait for desync: {}", e))?;
+    /// Turns an archive entry name into a relative path that cannot escape
+    /// the extraction directory.
+    fn sanitize_entry_path(name: &str) -> Result<PathBuf, String> {
+        use std::path::Component;
+
+        let normalized = name.replace('\\', "/");
+        let mut relative = PathBuf::new();
+        for component in Path::new(&normalized).components() {
+            match component {
+                Component::Normal(part) => relative.push(part),
+                Component::CurDir => {}
+                _ => return Err(format!("Refusing unsafe archive path: {}", name)),
+            }
+        }
 
-        eprintln!("[DEBUG] Process exited with status: {:?}", status);
+        if relative.as_os_str().is_empty() {
+            return Err(format!("Refusing empty archive path: {:?}", name));
+        }
+        Ok(relative)
+    }
 
-        if !status.success() {
-            return Err(format!("desync failed with status: {}", status));
+    fn apply_patch_payload<F>(
+        archive_path: &Path,
+        extracted: &[PathBuf],
+        pak_path: &Path,
+        cancel: &CancelToken,
+        output_callback: &mut F,
+    ) -> Result<(), PatcherError>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        // The payload is one diff for the pak, plus the tools that applied it
+        // before GYeet did so itself. Anything else would be a step GYeet
+        // does not know how to perform, so refuse rather than skip it
+        let invalid = |message: String| PatcherError::Archive {
+            path: archive_path.to_path_buf(),
+            message,
+        };
+        let mut diffs = Vec::new();
+        for path in extracted.iter().filter(|path| path.is_file()) {
+            if hdiff::is_hdiff_file(path) {
+                diffs.push(path);
+            } else if path.file_name() != pak_path.file_name()
+                && !path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| PATCH_TOOL_FILES.contains(&name))
+            {
+                return Err(invalid(format!(
+                    "Unexpected file in the patch payload: {}",
+                    path.display()
+                )));
+            }
+        }
+        let diff_path = match diffs.as_slice() {
+            [diff_path] => *diff_path,
+            [] => {
+                return Err(invalid(
+                    "No HDiffPatch diff found in the patch payload".to_string(),
+                ))
+            }
+            _ => {
+                return Err(invalid(format!(
+                    "The patch payload holds {} diffs, expected one",
+                    diffs.len()
+                )))
+            }
+        };
+        output_callback(ProgressEvent::info(format!(
+            "Diff file: {}",
+            diff_path.display()
+        )));
+
+        // Write the patched pak next to the original and swap it in only once
+        // the diff has been applied completely.
+        let new_pak = pak_path.with_extension("pak.gyeet_new");
+        if let Err(e) = hdiff::apply(pak_path, diff_path, &new_pak, cancel, output_callback) {
+            let _ = fs::remove_file(&new_pak);
+            return Err(e);
         }
 
-        output_callback("✅ Unpacking completed successfully".to_string());
-        eprintln!("[DEBUG] run_desync completed successfully");
+        fs::rename(&new_pak, pak_path).map_err(|e| {
+            let _ = fs::remove_file(&new_pak);
+            PatcherError::io("Failed to replace", pak_path)(e)
+        })?;
 
+        output_callback(ProgressEvent::info(format!(
+            "Patched: {}",
+            pak_path.display()
+        )));
         Ok(())
     }
 }
 
-// Fetch available game versions from the catalog
-pub fn fetch_game_versions() -> Result<Vec<GameVersion>, String> {
-    let client = reqwest::blocking::Client::new();
-    let response = client
-        .get(INSTALL_CATALOG_URL)
-        .send()
-        .map_err(|e| format!("Failed to fetch version catalog: {}", e))?;
+/// Reports how an update, restore or install ended, as its last event.
+fn finish<F>(
+    result: Result<String, PatcherError>,
+    output_callback: &mut F,
+) -> Result<String, PatcherError>
+where
+    F: FnMut(ProgressEvent),
+{
+    output_callback(ProgressEvent::Finished(result.clone()));
+    result
+}
 
-    let versions: Vec<GameVersion> = response
-        .json()
-        .map_err(|e| format!("Failed to parse JSON: {}", e))?;
+// Fetch available game versions from the catalog
+pub fn fetch_game_versions(
+    config: &Config,
+    cancel: &CancelToken,
+) -> Result<Vec<GameVersion>, PatcherError> {
+    let (body, _) = http::with_mirrors(&config.install_catalog_urls, "version catalog", |url| {
+        fetch_signed(config, url, "version catalog", cancel)
+    })?;
+
+    let versions: Vec<GameVersion> =
+        serde_json::from_slice(&body).map_err(|e| PatcherError::ManifestParse {
+            what: "version catalog".to_string(),
+            message: e.to_string(),
+        })?;
 
     Ok(versions)
-}
\ No newline at end of file
+}
+
+fn parse_manifest(body: &[u8], what: &str) -> Result<PatchManifest, PatcherError> {
+    serde_json::from_slice(body).map_err(|e| PatcherError::ManifestParse {
+        what: what.to_string(),
+        message: e.to_string(),
+    })
+}
+
+/// Downloads a manifest and checks its detached minisign signature, published
+/// next to it with a `.minisig` suffix. Returns the manifest and, unless
+/// unsigned manifests are allowed, the signature.
+fn fetch_signed(
+    config: &Config,
+    url: &str,
+    what: &str,
+    cancel: &CancelToken,
+) -> Result<(Vec<u8>, Option<String>), PatcherError> {
+    let body = http::send(cancel, |client| client.get(url))?
+        .and_then(|r| r.error_for_status())
+        .and_then(|r| r.bytes())
+        .map_err(PatcherError::network(url))?;
+
+    if config.allow_unsigned_manifests {
+        return Ok((body.to_vec(), None));
+    }
+
+    let signature_url = format!("{}.minisig", url);
+    let signature_text = http::send(cancel, |client| client.get(&signature_url))?
+        .and_then(|r| r.error_for_status())
+        .and_then(|r| r.text())
+        .map_err(|e| match e.status() {
+            Some(status) => PatcherError::Signature {
+                what: what.to_string(),
+                message: format!("no signature at {} (HTTP {})", signature_url, status),
+            },
+            None => PatcherError::network(&signature_url)(e),
+        })?;
+    check_signature(config, &body, &signature_text, what)?;
+
+    Ok((body.to_vec(), Some(signature_text)))
+}
+
+/// Checks `body` against a minisign signature made with the configured key.
+fn check_signature(
+    config: &Config,
+    body: &[u8],
+    signature_text: &str,
+    what: &str,
+) -> Result<(), PatcherError> {
+    let signature_error = |message: String| PatcherError::Signature {
+        what: what.to_string(),
+        message,
+    };
+    let key_text = Some(config.manifest_public_key.as_str())
+        .filter(|key| !key.trim().is_empty())
+        .unwrap_or(signature::EMBEDDED_PUBLIC_KEY);
+    let key = signature::PublicKey::parse(key_text)
+        .map_err(|e| signature_error(format!("invalid manifest signing key: {}", e)))?;
+
+    signature::verify(body, signature_text, &key).map_err(signature_error)
+}
@@ -0,0 +1,176 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::error::PatcherError;
+
+/// A step of an update, restore, install, repair or version switch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Identifying the installed version and planning the patches.
+    Check,
+    Backup,
+    Download,
+    Extract,
+    Patch,
+    Verify,
+    Restore,
+    /// Unpacking the game files from the chunk store.
+    Unpack,
+    /// Re-fetching game files that failed verification.
+    Repair,
+    /// Rewriting the game files that differ in the version switched to.
+    Switch,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Stage::Check => "Checking",
+            Stage::Backup => "Backing up",
+            Stage::Download => "Downloading",
+            Stage::Extract => "Extracting",
+            Stage::Patch => "Patching",
+            Stage::Verify => "Verifying",
+            Stage::Restore => "Restoring",
+            Stage::Unpack => "Unpacking",
+            Stage::Repair => "Repairing",
+            Stage::Switch => "Switching",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogLevel {
+    Info,
+    Success,
+    Error,
+}
+
+/// What a running operation reports back to whoever started it.
+#[derive(Debug, Clone)]
+pub enum ProgressEvent {
+    StageStarted(Stage),
+    StageFinished(Stage),
+    /// Bytes done out of `total` in the current stage.
+    Bytes {
+        done: u64,
+        total: u64,
+    },
+    /// Like `Bytes`, for transfers long enough that their speed matters.
+    /// `rate` is in bytes per second; `eta` is unknown until data flows.
+    Transfer {
+        done: u64,
+        total: u64,
+        rate: u64,
+        eta: Option<Duration>,
+    },
+    Log {
+        level: LogLevel,
+        message: String,
+    },
+    /// Something the user should know about that does not stop the operation.
+    Warning(String),
+    /// Always the last event of an update, restore, install or repair.
+    /// Holds the version the game is at afterwards.
+    Finished(Result<String, PatcherError>),
+}
+
+impl ProgressEvent {
+    pub fn info(message: impl Into<String>) -> Self {
+        ProgressEvent::Log {
+            level: LogLevel::Info,
+            message: message.into(),
+        }
+    }
+
+    pub fn success(message: impl Into<String>) -> Self {
+        ProgressEvent::Log {
+            level: LogLevel::Success,
+            message: message.into(),
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        ProgressEvent::Log {
+            level: LogLevel::Error,
+            message: message.into(),
+        }
+    }
+}
+
+/// Turns a growing byte count into `Transfer` events, with the rate taken
+/// over the last few seconds so the time left follows the connection.
+#[derive(Debug)]
+pub struct RateMeter {
+    total: u64,
+    started: Instant,
+    samples: VecDeque<(Instant, u64)>,
+    last_report: Option<Instant>,
+}
+
+impl RateMeter {
+    /// How far back the rate looks.
+    const WINDOW: Duration = Duration::from_secs(5);
+    /// Events closer together than this are skipped.
+    const REPORT_INTERVAL: Duration = Duration::from_millis(250);
+
+    pub fn new(total: u64) -> Self {
+        let started = Instant::now();
+        RateMeter {
+            total,
+            started,
+            samples: VecDeque::from([(started, 0)]),
+            last_report: None,
+        }
+    }
+
+    /// The event for `done` bytes, or None if the last one was sent too
+    /// recently. The final byte is always reported.
+    pub fn update(&mut self, done: u64) -> Option<ProgressEvent> {
+        let now = Instant::now();
+        let recent = self
+            .last_report
+            .is_some_and(|last| now - last < Self::REPORT_INTERVAL);
+        if recent && done < self.total {
+            return None;
+        }
+        self.last_report = Some(now);
+
+        self.samples.push_back((now, done));
+        while self.samples.len() > 2 && now - self.samples[1].0 >= Self::WINDOW {
+            self.samples.pop_front();
+        }
+        let (since, from) = self.samples[0];
+        let seconds = (now - since).as_secs_f64();
+        let rate = if seconds > 0.0 {
+            (done.saturating_sub(from) as f64 / seconds) as u64
+        } else {
+            0
+        };
+        let eta = (rate > 0).then(|| Duration::from_secs(self.total.saturating_sub(done) / rate));
+
+        Some(ProgressEvent::Transfer {
+            done,
+            total: self.total,
+            rate,
+            eta,
+        })
+    }
+
+    /// Time since the meter was created.
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+}
+
+/// A duration the way people say it, such as "2 min 5 s".
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match seconds {
+        0..=59 => format!("{} s", seconds),
+        60..=3599 => format!("{} min {} s", seconds / 60, seconds % 60),
+        _ => format!("{} h {} min", seconds / 3600, seconds % 3600 / 60),
+    }
+}
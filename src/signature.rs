@@ -0,0 +1,177 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use blake2::{Blake2b512, Digest};
+use ring::signature::{UnparsedPublicKey, ED25519};
+
+/// Key used to check manifest signatures when none is set in the config.
+/// If this fails to build, `assets/manifest_signing.pub` is missing: it must
+/// hold the minisign public key the manifests on votv.dev are signed with.
+pub const EMBEDDED_PUBLIC_KEY: &str = include_str!("../assets/manifest_signing.pub");
+
+/// A minisign public key.
+pub struct PublicKey {
+    key_id: [u8; 8],
+    key: [u8; 32],
+}
+
+impl PublicKey {
+    /// Parses a key in minisign's format, either the bare base64 line or the
+    /// whole `.pub` file including its comment.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let line = text
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with("untrusted comment:"))
+            .ok_or("Public key is empty")?;
+        let bytes = BASE64
+            .decode(line)
+            .map_err(|e| format!("Public key is not valid base64: {}", e))?;
+        if bytes.len() != 42 || &bytes[..2] != b"Ed" {
+            return Err("Not an Ed25519 minisign public key".to_string());
+        }
+
+        let mut key_id = [0u8; 8];
+        let mut key = [0u8; 32];
+        key_id.copy_from_slice(&bytes[2..10]);
+        key.copy_from_slice(&bytes[10..]);
+        Ok(PublicKey { key_id, key })
+    }
+
+    fn verify_raw(&self, message: &[u8], signature: &[u8]) -> bool {
+        UnparsedPublicKey::new(&ED25519, &self.key)
+            .verify(message, signature)
+            .is_ok()
+    }
+}
+
+/// Checks a detached minisign signature over `data`, including the signed
+/// trusted comment.
+pub fn verify(data: &[u8], signature_text: &str, key: &PublicKey) -> Result<(), String> {
+    let mut lines = signature_text.lines().map(str::trim_end);
+
+    let mut next_line = |what: &str| {
+        lines
+            .by_ref()
+            .find(|line| !line.is_empty())
+            .ok_or(format!("Signature is missing its {}", what))
+    };
+    let untrusted = next_line("header")?;
+    if !untrusted.starts_with("untrusted comment:") {
+        return Err("Signature does not start with an untrusted comment".to_string());
+    }
+    let signature_line = next_line("signature")?;
+    let trusted_comment = next_line("trusted comment")?
+        .strip_prefix("trusted comment: ")
+        .ok_or("Signature has no trusted comment")?;
+    let global_line = next_line("global signature")?;
+
+    let signature = BASE64
+        .decode(signature_line)
+        .map_err(|e| format!("Signature is not valid base64: {}", e))?;
+    if signature.len() != 74 {
+        return Err("Signature has the wrong length".to_string());
+    }
+    let (algorithm, rest) = signature.split_at(2);
+    let (key_id, signature) = rest.split_at(8);
+    if key_id != key.key_id {
+        return Err("Signature was made with a different key".to_string());
+    }
+
+    let verified = match algorithm {
+        b"Ed" => key.verify_raw(data, signature),
+        b"ED" => key.verify_raw(&Blake2b512::digest(data), signature),
+        _ => return Err("Unsupported signature algorithm".to_string()),
+    };
+    if !verified {
+        return Err("Signature does not match the data".to_string());
+    }
+
+    let global = BASE64
+        .decode(global_line)
+        .map_err(|e| format!("Global signature is not valid base64: {}", e))?;
+    let mut signed_comment = signature.to_vec();
+    signed_comment.extend_from_slice(trusted_comment.as_bytes());
+    if !key.verify_raw(&signed_comment, &global) {
+        return Err("Trusted comment signature does not match".to_string());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Key and signatures over the bytes `test` made by minisign itself, one
+    // over the data as is and one over its BLAKE2b-512 hash
+    const KEY: &str = "untrusted comment: minisign public key E7620F1842B4E81F
+RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3";
+    const LEGACY_SIGNATURE: &str = "untrusted comment: signature from minisign secret key
+RWQf6LRCGA9i59SLOFxz6NxvASXDJeRtuZykwQepbDEGt87ig1BNpWaVWuNrm73YiIiJbq71Wi+dP9eKL8OC351vwIasSSbXxwA=
+trusted comment: timestamp:1555779966\tfile:test
+QtKMXWyYcwdpZAlPF7tE2ENJkRd1ujvKjlj1m9RtHTBnZPa5WKU5uWRs5GoP5M/VqE81QFuMKI5k/SfNQUaOAA==";
+    const PREHASHED_SIGNATURE: &str = "untrusted comment: signature from minisign secret key
+RUQf6LRCGA9i559r3g7V1qNyJDApGip8MfqcadIgT9CuhV3EMhHoN1mGTkUidF/z7SrlQgXdy8ofjb7bNJJylDOocrCo8KLzZwo=
+trusted comment: timestamp:1556193335\tfile:test
+y/rUw2y8/hOUYjZU71eHp/Wo1KZ40fGy2VJEDl34XMJM+TX48Ss/17u3IvIfbVR1FkZZSNCisQbuQY+bHwhEBg==";
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn blake2b_known_answers() {
+        assert_eq!(
+            hex(&Blake2b512::digest(b"")),
+            "786a02f742015903c6c6fd852552d272912f4740e15847618a86e217f71f5419\
+             d25e1031afee585313896444934eb04b903a685b1448b755d56f701afe9be2ce"
+        );
+        assert_eq!(
+            hex(&Blake2b512::digest(b"abc")),
+            "ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d1\
+             7d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925ab92386edd4009923"
+        );
+    }
+
+    #[test]
+    fn accepts_minisign_signatures() {
+        let key = PublicKey::parse(KEY).unwrap();
+        assert_eq!(verify(b"test", PREHASHED_SIGNATURE, &key), Ok(()));
+        assert_eq!(verify(b"test", LEGACY_SIGNATURE, &key), Ok(()));
+    }
+
+    #[test]
+    fn rejects_tampered_data() {
+        let key = PublicKey::parse(KEY).unwrap();
+        assert_eq!(
+            verify(b"Test", PREHASHED_SIGNATURE, &key),
+            Err("Signature does not match the data".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_tampered_trusted_comment() {
+        let key = PublicKey::parse(KEY).unwrap();
+        let tampered = PREHASHED_SIGNATURE.replace("file:test", "file:tset");
+        assert_eq!(
+            verify(b"test", &tampered, &key),
+            Err("Trusted comment signature does not match".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_other_keys() {
+        // Same key bytes under a different key id
+        let key =
+            PublicKey::parse("RWQAAAAAAAAAAHmlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3").unwrap();
+        assert_eq!(
+            verify(b"test", PREHASHED_SIGNATURE, &key),
+            Err("Signature was made with a different key".to_string())
+        );
+    }
+
+    #[test]
+    fn embedded_key_parses() {
+        PublicKey::parse(EMBEDDED_PUBLIC_KEY).unwrap();
+    }
+}
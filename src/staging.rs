@@ -0,0 +1,92 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::PatcherError;
+
+/// A fresh, uniquely named directory for one operation's scratch files.
+/// It is removed when dropped, so it goes away on success, failure and
+/// cancel alike. Staging never happens inside the game directory.
+#[derive(Debug)]
+pub struct StagingDir {
+    path: PathBuf,
+}
+
+impl StagingDir {
+    /// Where staging directories are created: next to the download cache,
+    /// so extracting a cached patch stays on the same filesystem.
+    pub fn root() -> PathBuf {
+        dirs::cache_dir()
+            .unwrap_or_else(std::env::temp_dir)
+            .join("gyeet")
+            .join("staging")
+    }
+
+    /// Creates a new staging directory whose name starts with `purpose`.
+    pub fn create(purpose: &str) -> Result<Self, PatcherError> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+        let root = Self::root();
+        fs::create_dir_all(&root).map_err(PatcherError::io(
+            "Failed to create staging directory",
+            &root,
+        ))?;
+        remove_abandoned(&root);
+
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(0);
+        loop {
+            let name = format!(
+                "{}-{}-{:08x}-{}",
+                purpose,
+                std::process::id(),
+                nanos,
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            );
+            let path = root.join(name);
+            // create_dir rather than create_dir_all: the directory must be new
+            match fs::create_dir(&path) {
+                Ok(()) => return Ok(StagingDir { path }),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => {
+                    return Err(PatcherError::io(
+                        "Failed to create staging directory",
+                        &path,
+                    )(e))
+                }
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for StagingDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Removes staging directories left behind by GYeet processes that are no
+/// longer running, such as one that crashed.
+fn remove_abandoned(root: &Path) {
+    let Ok(items) = fs::read_dir(root) else {
+        return;
+    };
+    for item in items.flatten() {
+        let name = item.file_name();
+        let pid = name.to_str().and_then(|name| name.split('-').nth(1));
+        let Some(pid) = pid.and_then(|pid| pid.parse::<u32>().ok()) else {
+            continue;
+        };
+        if pid != std::process::id() && !Path::new("/proc").join(pid.to_string()).exists() {
+            let _ = fs::remove_dir_all(item.path());
+        }
+    }
+}
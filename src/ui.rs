@@ -1,16 +1,22 @@
 use gtk4::glib;
 use gtk4::prelude::*;
 use gtk4::{
-    Application, ApplicationWindow, Box as GtkBox, Button, ButtonsType, ComboBoxText, Entry,
-    FileChooserAction, FileChooserDialog, Label, MessageDialog, MessageType, Notebook, Orientation,
-    ResponseType, ScrolledWindow, TextView,
+    Application, ApplicationWindow, Box as GtkBox, Button, ButtonsType, CheckButton, ComboBoxText,
+    Entry, FileChooserAction, FileChooserDialog, Label, MessageDialog, MessageType, Notebook,
+    Orientation, ProgressBar, ResponseType, ScrolledWindow, SpinButton, TextView,
 };
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::sync::mpsc;
 
+use crate::cache::{CacheUsage, ChunkCache, DownloadCache};
+use crate::cancel::CancelToken;
 use crate::config::Config;
-use crate::patcher::Patcher;
+use crate::disk::format_size;
+use crate::error::PatcherError;
+use crate::patcher::{PatchSource, Patcher, UpdateReport};
+use crate::progress::{self, LogLevel, ProgressEvent};
 use crate::proton::ProtonLauncher;
 
 pub fn build_ui(app: &Application) {
@@ -56,11 +62,45 @@ pub fn build_ui(app: &Application) {
     console.add_css_class("console");
     console_scroll.set_child(Some(&console));
 
+    // Progress of the running task (shared across tabs)
+    let progress = ProgressBar::new();
+    progress.set_show_text(true);
+    progress.set_visible(false);
+
+    // One task runs at a time (shared across tabs)
+    let cancel = CancelControl::new();
+
+    // Version of the game the Patch tab works on; the Install tab updates
+    // it when it installs or switches that same game
+    let version_label = Label::new(Some(&installed_version_text(
+        &config.borrow().installed_version,
+    )));
+
     // Create tabs
-    let patch_tab = create_patch_tab(config.clone(), console.clone(), window.clone());
-    let install_tab = create_install_tab(config.clone(), console.clone(), window.clone());
+    let patch_tab = create_patch_tab(
+        config.clone(),
+        console.clone(),
+        progress.clone(),
+        window.clone(),
+        cancel.clone(),
+        version_label.clone(),
+    );
+    let install_tab = create_install_tab(
+        config.clone(),
+        console.clone(),
+        progress.clone(),
+        window.clone(),
+        cancel.clone(),
+        version_label,
+    );
     let launch_tab = create_launch_tab(config.clone(), console.clone(), window.clone());
-    let settings_tab = create_settings_tab(config.clone(), console.clone(), window.clone());
+    let settings_tab = create_settings_tab(
+        config.clone(),
+        console.clone(),
+        progress.clone(),
+        window.clone(),
+        cancel,
+    );
 
     notebook.append_page(&patch_tab, Some(&Label::new(Some("Patch/Update"))));
     notebook.append_page(&install_tab, Some(&Label::new(Some("Install"))));
@@ -68,6 +108,7 @@ pub fn build_ui(app: &Application) {
     notebook.append_page(&settings_tab, Some(&Label::new(Some("Settings"))));
 
     main_box.append(&notebook);
+    main_box.append(&progress);
 
     // Console section
     let console_label = Label::new(Some("Console Output"));
@@ -83,7 +124,10 @@ pub fn build_ui(app: &Application) {
 fn create_patch_tab(
     config: Rc<RefCell<Config>>,
     console: TextView,
+    progress: ProgressBar,
     window: ApplicationWindow,
+    cancel: CancelControl,
+    version_label: Label,
 ) -> GtkBox {
     let vbox = GtkBox::new(Orientation::Vertical, 15);
     vbox.set_margin_top(15);
@@ -118,69 +162,435 @@ fn create_patch_tab(
     path_box.append(&browse_btn);
     vbox.append(&path_box);
 
+    version_label.set_halign(gtk4::Align::Start);
+    vbox.append(&version_label);
+
     // Also save when text is changed manually
     let config_clone = config.clone();
     let entry_clone = votv_path_entry.clone();
+    let version_label_clone = version_label.clone();
     votv_path_entry.connect_changed(move |_| {
         let new_path = entry_clone.text().to_string();
+        if new_path != config_clone.borrow().votv_exe_path {
+            // The recorded version belonged to the previous install
+            config_clone.borrow_mut().installed_version.clear();
+            version_label_clone.set_text(&installed_version_text(""));
+        }
         config_clone.borrow_mut().votv_exe_path = new_path;
         if let Err(e) = config_clone.borrow().save() {
             eprintln!("Failed to save config: {}", e);
         }
     });
 
+    // Optional target version
+    let target_label = Label::new(Some("Target Version"));
+    target_label.set_halign(gtk4::Align::Start);
+    target_label.set_margin_top(10);
+    vbox.append(&target_label);
+
+    let target_entry = Entry::new();
+    target_entry.set_placeholder_text(Some("Leave empty to update to the latest version"));
+    vbox.append(&target_entry);
+
+    // The pak is normally only hashed again after it changed on disk
+    let rehash_check = CheckButton::with_label("Force re-hash of game files");
+    rehash_check.set_tooltip_text(Some(
+        "Hash the pak file again even if it looks unchanged since the last check",
+    ));
+    vbox.append(&rehash_check);
+
     // Patch button
     let patch_btn = Button::with_label("Check for Updates & Patch");
     patch_btn.add_css_class("primary-button");
     patch_btn.set_margin_top(20);
 
-    let _config_clone = config.clone();
+    let config_clone = config.clone();
     let console_clone = console.clone();
+    let progress_clone = progress.clone();
+    let cancel_clone = cancel.clone();
     let window_clone = window.clone();
     let entry_clone = votv_path_entry.clone();
-    let patch_btn_clone = patch_btn.clone();
+    let target_clone = target_entry.clone();
+    let rehash_clone = rehash_check.clone();
+    let version_label_clone = version_label.clone();
     patch_btn.connect_clicked(move |_| {
         let votv_path = entry_clone.text().to_string();
+        let target_version = target_clone.text().trim().to_string();
         if votv_path.is_empty() {
             show_error(&window_clone, "Please select VotV.exe first!");
             return;
         }
 
-        log_to_console(&console_clone, "Starting patch process...");
-        patch_btn_clone.set_sensitive(false);
+        let target_version = (!target_version.is_empty()).then_some(target_version);
+        check_then_update(
+            &votv_path,
+            PatchSource::Online { target_version },
+            rehash_clone.is_active(),
+            &config_clone,
+            &console_clone,
+            &progress_clone,
+            &cancel_clone,
+            &window_clone,
+            &version_label_clone,
+        );
+    });
+    cancel.guard(&patch_btn);
+    vbox.append(&patch_btn);
 
-        let (tx, rx) = mpsc::channel::<String>();
-        setup_progress_receiver(rx, console_clone.clone(), Some(patch_btn_clone.clone()));
+    // Offline patching from a downloaded archive
+    let offline_label = Label::new(Some("Patch from File (offline)"));
+    offline_label.set_halign(gtk4::Align::Start);
+    offline_label.set_margin_top(20);
+    vbox.append(&offline_label);
 
-        let votv_path_clone = votv_path.clone();
-        let tx_clone = tx.clone();
-        std::thread::spawn(move || {
-            let patcher = Patcher::new();
-            let result = patcher.run_update(&votv_path_clone, |line| {
-                if tx_clone.send(line.to_string()).is_err() {
-                    // Stop if receiver has been dropped
-                    return;
+    let archive_box = GtkBox::new(Orientation::Horizontal, 10);
+    let archive_entry = Entry::new();
+    archive_entry.set_placeholder_text(Some("Patch archive (.7z)..."));
+    archive_entry.set_hexpand(true);
+    archive_box.append(&archive_entry);
+
+    let browse_archive_btn = Button::with_label("Browse...");
+    let window_clone = window.clone();
+    let entry_clone = archive_entry.clone();
+    browse_archive_btn.connect_clicked(move |_| {
+        browse_file(&window_clone, &entry_clone, "Select Patch Archive", "*.7z");
+    });
+    archive_box.append(&browse_archive_btn);
+    vbox.append(&archive_box);
+
+    let manifest_box = GtkBox::new(Orientation::Horizontal, 10);
+    let manifest_entry = Entry::new();
+    manifest_entry.set_placeholder_text(Some(
+        "Patch manifest (optional, defaults to the last downloaded one)...",
+    ));
+    manifest_entry.set_hexpand(true);
+    manifest_box.append(&manifest_entry);
+
+    let browse_manifest_btn = Button::with_label("Browse...");
+    let window_clone = window.clone();
+    let entry_clone = manifest_entry.clone();
+    browse_manifest_btn.connect_clicked(move |_| {
+        browse_file(
+            &window_clone,
+            &entry_clone,
+            "Select Patch Manifest",
+            "*.json",
+        );
+    });
+    manifest_box.append(&browse_manifest_btn);
+    vbox.append(&manifest_box);
+
+    let file_patch_btn = Button::with_label("Patch from File");
+
+    let config_clone = config.clone();
+    let console_clone = console.clone();
+    let progress_clone = progress.clone();
+    let cancel_clone = cancel.clone();
+    let window_clone = window.clone();
+    let entry_clone = votv_path_entry.clone();
+    let archive_clone = archive_entry.clone();
+    let manifest_clone = manifest_entry.clone();
+    let rehash_clone = rehash_check.clone();
+    let version_label_clone = version_label.clone();
+    file_patch_btn.connect_clicked(move |_| {
+        let votv_path = entry_clone.text().to_string();
+        let archive = archive_clone.text().trim().to_string();
+        let manifest = manifest_clone.text().trim().to_string();
+        if votv_path.is_empty() {
+            show_error(&window_clone, "Please select VotV.exe first!");
+            return;
+        }
+        if archive.is_empty() {
+            show_error(&window_clone, "Please select a patch archive first!");
+            return;
+        }
+
+        let source = PatchSource::Local {
+            archive: PathBuf::from(archive),
+            manifest: (!manifest.is_empty()).then(|| PathBuf::from(manifest)),
+        };
+        check_then_update(
+            &votv_path,
+            source,
+            rehash_clone.is_active(),
+            &config_clone,
+            &console_clone,
+            &progress_clone,
+            &cancel_clone,
+            &window_clone,
+            &version_label_clone,
+        );
+    });
+    cancel.guard(&file_patch_btn);
+    vbox.append(&file_patch_btn);
+    vbox.append(&cancel.cancel_button());
+
+    // Restore button
+    let restore_btn = Button::with_label("Restore Previous Version");
+
+    let config_clone = config.clone();
+    let console_clone = console.clone();
+    let progress_clone = progress.clone();
+    let cancel_clone = cancel.clone();
+    let window_clone = window.clone();
+    let entry_clone = votv_path_entry.clone();
+    let version_label_clone = version_label.clone();
+    restore_btn.connect_clicked(move |_| {
+        let votv_path = entry_clone.text().to_string();
+        if votv_path.is_empty() {
+            show_error(&window_clone, "Please select VotV.exe first!");
+            return;
+        }
+
+        let config = config_clone.clone();
+        let console = console_clone.clone();
+        let progress = progress_clone.clone();
+        let cancel = cancel_clone.clone();
+        let version_label = version_label_clone.clone();
+        let question = "Put back the game files saved before the last update?";
+        confirm(&window_clone, question, move |accepted| {
+            if !accepted || !cancel.lock() {
+                return;
+            }
+            log_to_console(&console, "Restoring previous version...");
+
+            let (tx, rx) = mpsc::channel::<ProgressEvent>();
+            let config_snapshot = config.borrow().clone();
+            let config = config.clone();
+            let version_label = version_label.clone();
+            let cancel = cancel.clone();
+            let console_clone = console.clone();
+            setup_progress_receiver(rx, console.clone(), progress.clone(), move |outcome| {
+                cancel.finish();
+                if let Some(version) = log_outcome(&console_clone, outcome, "Restore") {
+                    set_installed_version(&config, &version_label, &version);
                 }
             });
 
-            let msg = match result {
-                Ok(0) => "Patch completed successfully!".to_string(),
-                Ok(code) => format!("Patch exited with code: {}", code),
-                Err(e) => format!("Patch failed: {}", e),
-            };
-            let _ = tx.send(msg);
-            let _ = tx.send("DONE".to_string());
+            let votv_path = votv_path.clone();
+            std::thread::spawn(move || {
+                let patcher = Patcher::new(config_snapshot);
+                let _ = patcher.restore_previous(&votv_path, |event| {
+                    let _ = tx.send(event);
+                });
+            });
         });
     });
-    vbox.append(&patch_btn);
+    cancel.guard(&restore_btn);
+    vbox.append(&restore_btn);
+
+    // Verify & Repair button
+    let repair_btn = Button::with_label("Verify & Repair");
+    repair_btn.set_tooltip_text(Some(
+        "Compare the game files with the install index and re-download damaged ones",
+    ));
+
+    let config_clone = config.clone();
+    let console_clone = console.clone();
+    let progress_clone = progress.clone();
+    let cancel_clone = cancel.clone();
+    let window_clone = window.clone();
+    let entry_clone = votv_path_entry.clone();
+    let version_label_clone = version_label.clone();
+    repair_btn.connect_clicked(move |_| {
+        let votv_path = entry_clone.text().to_string();
+        if votv_path.is_empty() {
+            show_error(&window_clone, "Please select VotV.exe first!");
+            return;
+        }
+
+        let Some(token) = cancel_clone.start() else {
+            return;
+        };
+        log_to_console(&console_clone, "Verifying game files...");
+
+        let (tx, rx) = mpsc::channel::<ProgressEvent>();
+        let config = config_clone.clone();
+        let console = console_clone.clone();
+        let cancel = cancel_clone.clone();
+        let version_label = version_label_clone.clone();
+        setup_progress_receiver(
+            rx,
+            console.clone(),
+            progress_clone.clone(),
+            move |outcome| {
+                cancel.finish();
+                if let Some(version) = log_outcome(&console, outcome, "Repair") {
+                    set_installed_version(&config, &version_label, &version);
+                }
+            },
+        );
+
+        let config_snapshot = config_clone.borrow().clone();
+        std::thread::spawn(move || {
+            let patcher = Patcher::new(config_snapshot);
+            let _ = patcher.run_repair(&votv_path, &token, |event| {
+                let _ = tx.send(event);
+            });
+        });
+    });
+    cancel.guard(&repair_btn);
+    vbox.append(&repair_btn);
 
     vbox
 }
 
+/// Checks what an update from `source` would do, then asks before applying it.
+/// The tab stays locked from the check until the update is over, and the
+/// check and the update share one cancel token.
+#[allow(clippy::too_many_arguments)]
+fn check_then_update(
+    votv_path: &str,
+    source: PatchSource,
+    force_rehash: bool,
+    config: &Rc<RefCell<Config>>,
+    console: &TextView,
+    progress: &ProgressBar,
+    cancel: &CancelControl,
+    window: &ApplicationWindow,
+    version_label: &Label,
+) {
+    let Some(token) = cancel.start() else {
+        return;
+    };
+    log_to_console(console, "Checking for updates...");
+
+    // Check first, then ask before downloading and patching anything
+    let (tx, rx) = mpsc::channel::<ProgressEvent>();
+    setup_progress_receiver(rx, console.clone(), progress.clone(), |_| {});
+    let (report_tx, report_rx) = mpsc::channel::<Result<UpdateReport, PatcherError>>();
+
+    let votv_path = votv_path.to_string();
+    let votv_path_clone = votv_path.clone();
+    let source_clone = source.clone();
+    let token_clone = token.clone();
+    let config_snapshot = config.borrow().clone();
+    std::thread::spawn(move || {
+        let patcher = Patcher::new(config_snapshot);
+        let result = patcher.check_update(
+            &votv_path_clone,
+            &source_clone,
+            force_rehash,
+            &token_clone,
+            |event| {
+                let _ = tx.send(event);
+            },
+        );
+        let _ = report_tx.send(result);
+    });
+
+    let config = config.clone();
+    let console = console.clone();
+    let progress = progress.clone();
+    let cancel = cancel.clone();
+    let window = window.clone();
+    let version_label = version_label.clone();
+    glib::idle_add_local(move || {
+        let result = match report_rx.try_recv() {
+            Ok(result) => result,
+            Err(mpsc::TryRecvError::Empty) => return glib::ControlFlow::Continue,
+            Err(mpsc::TryRecvError::Disconnected) => {
+                cancel.finish();
+                return glib::ControlFlow::Break;
+            }
+        };
+
+        if let Ok(report) = &result {
+            set_installed_version(&config, &version_label, &report.current_version);
+        }
+
+        match result {
+            Ok(report) if report.patch_available() => {
+                let question = format!("{}\n\nProceed with the update?", report.summary());
+                let votv_path = votv_path.clone();
+                let source = source.clone();
+                let config = config.clone();
+                let console = console.clone();
+                let progress = progress.clone();
+                let cancel = cancel.clone();
+                let token = token.clone();
+                let version_label = version_label.clone();
+                confirm(&window, &question, move |accepted| {
+                    if accepted {
+                        start_update(
+                            &votv_path,
+                            &source,
+                            &config,
+                            &console,
+                            &progress,
+                            &cancel,
+                            token.clone(),
+                            &version_label,
+                        );
+                    } else {
+                        log_to_console(&console, "Update cancelled.");
+                        cancel.finish();
+                    }
+                });
+            }
+            Ok(report) => {
+                show_info(&window, &report.summary());
+                cancel.finish();
+            }
+            Err(e) if e.is_cancelled() => {
+                log_to_console(&console, "Update check cancelled.");
+                cancel.finish();
+            }
+            Err(e) => {
+                log_to_console(&console, &describe_error("❌ Update check failed", &e));
+                cancel.finish();
+            }
+        }
+        glib::ControlFlow::Break
+    });
+}
+
+/// Runs the update that `check_then_update` planned, under the token the
+/// check was started with.
+#[allow(clippy::too_many_arguments)]
+fn start_update(
+    votv_path: &str,
+    source: &PatchSource,
+    config: &Rc<RefCell<Config>>,
+    console: &TextView,
+    progress: &ProgressBar,
+    cancel: &CancelControl,
+    token: CancelToken,
+    version_label: &Label,
+) {
+    log_to_console(console, "Starting patch process...");
+
+    let (tx, rx) = mpsc::channel::<ProgressEvent>();
+    let config_clone = config.clone();
+    let console_clone = console.clone();
+    let cancel = cancel.clone();
+    let version_label = version_label.clone();
+    setup_progress_receiver(rx, console.clone(), progress.clone(), move |outcome| {
+        cancel.finish();
+        if let Some(version) = log_outcome(&console_clone, outcome, "Patch") {
+            set_installed_version(&config_clone, &version_label, &version);
+        }
+    });
+
+    let votv_path = votv_path.to_string();
+    let source = source.clone();
+    let config_snapshot = config.borrow().clone();
+    std::thread::spawn(move || {
+        let patcher = Patcher::new(config_snapshot);
+        let _ = patcher.run_update(&votv_path, &source, &token, |event| {
+            // Ignore send errors if the receiver has been dropped
+            let _ = tx.send(event);
+        });
+    });
+}
+
 fn create_install_tab(
     config: Rc<RefCell<Config>>,
     console: TextView,
+    progress: ProgressBar,
     window: ApplicationWindow,
+    cancel: CancelControl,
+    installed_label: Label,
 ) -> GtkBox {
     let vbox = GtkBox::new(Orientation::Vertical, 15);
     vbox.set_margin_top(15);
@@ -232,13 +642,14 @@ fn create_install_tab(
     let combo_clone = version_combo.clone();
     let console_clone2 = console.clone();
     let versions_rc = available_versions.clone();
+    let config_clone = config.clone();
     refresh_btn.connect_clicked(move |_| {
         log_to_console(&console_clone2, "Fetching available versions...");
         combo_clone.remove_all();
         combo_clone.append_text("Loading...");
         combo_clone.set_active(Some(0));
 
-        let (tx, rx) = mpsc::channel::<Result<Vec<crate::patcher::GameVersion>, String>>();
+        let (tx, rx) = mpsc::channel::<Result<Vec<crate::patcher::GameVersion>, PatcherError>>();
         let combo = combo_clone.clone();
         let console = console_clone2.clone();
         let versions = versions_rc.clone();
@@ -262,7 +673,10 @@ fn create_install_tab(
                         combo.remove_all();
                         combo.append_text("Error - try again");
                         combo.set_active(Some(0));
-                        log_to_console(&console, &format!("❌ Failed to fetch versions: {}", e));
+                        log_to_console(
+                            &console,
+                            &describe_error("❌ Failed to fetch versions", &e),
+                        );
                     }
                 }
                 glib::ControlFlow::Break
@@ -271,8 +685,10 @@ fn create_install_tab(
             }
         });
 
+        let config_snapshot = config_clone.borrow().clone();
         std::thread::spawn(move || {
-            let result = crate::patcher::fetch_game_versions();
+            // Nothing waits on the list, so it is not tied to the Cancel button
+            let result = crate::patcher::fetch_game_versions(&config_snapshot, &CancelToken::new());
             let _ = tx.send(result);
         });
     });
@@ -285,11 +701,14 @@ fn create_install_tab(
     install_btn.set_margin_top(20);
 
     let console_clone = console.clone();
+    let progress_clone = progress.clone();
     let window_clone = window.clone();
     let entry_clone = install_dir_entry.clone();
     let combo_clone = version_combo.clone();
     let versions_clone = available_versions.clone();
-    let install_btn_clone = install_btn.clone();
+    let cancel_clone = cancel.clone();
+    let config_clone = config.clone();
+    let installed_label_clone = installed_label.clone();
     install_btn.connect_clicked(move |_| {
         let install_dir = entry_clone.text().to_string();
         if install_dir.is_empty() {
@@ -300,63 +719,146 @@ fn create_install_tab(
             return;
         }
 
-        // Get selected version
-        let selected_idx = match combo_clone.active() {
-            Some(idx) => idx as usize,
-            None => {
-                show_error(&window_clone, "Please select a version first!");
-                return;
-            }
-        };
-
-        let versions = versions_clone.borrow();
-        if versions.is_empty() {
-            show_error(
-                &window_clone,
-                "Please click Refresh to load versions first!",
-            );
+        let Some(selected_version) = selected_version(&window_clone, &combo_clone, &versions_clone)
+        else {
             return;
-        }
+        };
 
-        if selected_idx >= versions.len() {
-            show_error(&window_clone, "Invalid version selection!");
+        let Some(token) = cancel_clone.start() else {
             return;
-        }
-
-        let selected_version = versions[selected_idx].clone();
-        drop(versions); // Release the borrow
-
+        };
         log_to_console(&console_clone, "Starting install process...");
-        install_btn_clone.set_sensitive(false);
 
-        let (tx, rx) = mpsc::channel::<String>();
-        setup_progress_receiver(rx, console_clone.clone(), Some(install_btn_clone.clone()));
+        let (tx, rx) = mpsc::channel::<ProgressEvent>();
+        let console = console_clone.clone();
+        let cancel = cancel_clone.clone();
+        let config = config_clone.clone();
+        let installed_label = installed_label_clone.clone();
+        let installed_dir = install_dir.clone();
+        setup_progress_receiver(
+            rx,
+            console_clone.clone(),
+            progress_clone.clone(),
+            move |outcome| {
+                cancel.finish();
+                if let Some(version) = log_outcome(&console, outcome, "Install") {
+                    record_if_patched_game(&config, &installed_label, &installed_dir, &version);
+                }
+            },
+        );
 
         let install_dir_clone = install_dir.clone();
-        let tx_clone = tx.clone();
+        let config_snapshot = config_clone.borrow().clone();
         std::thread::spawn(move || {
-            let patcher = Patcher::new();
-            let result = patcher.run_install(&install_dir_clone, &selected_version, move |line| {
-                if tx_clone.send(line.to_string()).is_err() {
-                    // Stop if receiver has been dropped
-                    return;
-                }
+            let patcher = Patcher::new(config_snapshot);
+            let _ = patcher.run_install(&install_dir_clone, &selected_version, &token, |event| {
+                // Ignore send errors if the receiver has been dropped
+                let _ = tx.send(event);
             });
+        });
+    });
+    cancel.guard(&install_btn);
+    vbox.append(&install_btn);
+
+    // Switch version button
+    let switch_btn = Button::with_label("Switch Version");
+    switch_btn.set_tooltip_text(Some(
+        "Turn the install in this directory into the selected version, rewriting only the files that differ",
+    ));
 
-            let msg = match result {
-                Ok(0) => "Install completed successfully!".to_string(),
-                Ok(code) => format!("Install exited with code: {}", code),
-                Err(e) => format!("Install failed: {}", e),
+    let console_clone = console.clone();
+    let progress_clone = progress.clone();
+    let window_clone = window.clone();
+    let entry_clone = install_dir_entry.clone();
+    let combo_clone = version_combo.clone();
+    let versions_clone = available_versions.clone();
+    let cancel_clone = cancel.clone();
+    let config_clone = config.clone();
+    let installed_label_clone = installed_label.clone();
+    switch_btn.connect_clicked(move |_| {
+        let install_dir = entry_clone.text().to_string();
+        if install_dir.is_empty() {
+            show_error(&window_clone, "Please select the install to switch first!");
+            return;
+        }
+        let Some(selected_version) = selected_version(&window_clone, &combo_clone, &versions_clone)
+        else {
+            return;
+        };
+
+        let console = console_clone.clone();
+        let progress = progress_clone.clone();
+        let cancel = cancel_clone.clone();
+        let config = config_clone.clone();
+        let installed_label = installed_label_clone.clone();
+        let question = format!(
+            "Switch the install in {} to version {}? Game files that version does not have will be deleted.",
+            install_dir, selected_version.name
+        );
+        confirm(&window_clone, &question, move |accepted| {
+            if !accepted {
+                return;
+            }
+            let Some(token) = cancel.start() else {
+                return;
             };
-            let _ = tx.send(msg);
-            let _ = tx.send("DONE".to_string());
+            log_to_console(&console, "Starting version switch...");
+
+            let (tx, rx) = mpsc::channel::<ProgressEvent>();
+            let console_clone = console.clone();
+            let cancel = cancel.clone();
+            let config_clone = config.clone();
+            let installed_label = installed_label.clone();
+            let switched_dir = install_dir.clone();
+            setup_progress_receiver(rx, console.clone(), progress.clone(), move |outcome| {
+                cancel.finish();
+                if let Some(version) = log_outcome(&console_clone, outcome, "Version switch") {
+                    record_if_patched_game(&config_clone, &installed_label, &switched_dir, &version);
+                }
+            });
+
+            let install_dir = install_dir.clone();
+            let selected_version = selected_version.clone();
+            let config_snapshot = config.borrow().clone();
+            std::thread::spawn(move || {
+                let patcher = Patcher::new(config_snapshot);
+                let _ = patcher.run_switch_version(&install_dir, &selected_version, &token, |event| {
+                    let _ = tx.send(event);
+                });
+            });
         });
     });
-    vbox.append(&install_btn);
+    cancel.guard(&switch_btn);
+    vbox.append(&switch_btn);
+    vbox.append(&cancel.cancel_button());
 
     vbox
 }
 
+/// The version picked in `combo`, or None after telling the user why there
+/// is none.
+fn selected_version(
+    window: &ApplicationWindow,
+    combo: &ComboBoxText,
+    versions: &RefCell<Vec<crate::patcher::GameVersion>>,
+) -> Option<crate::patcher::GameVersion> {
+    let Some(selected_idx) = combo.active() else {
+        show_error(window, "Please select a version first!");
+        return None;
+    };
+
+    let versions = versions.borrow();
+    if versions.is_empty() {
+        show_error(window, "Please click Refresh to load versions first!");
+        return None;
+    }
+    let Some(version) = versions.get(selected_idx as usize) else {
+        show_error(window, "Invalid version selection!");
+        return None;
+    };
+    Some(version.clone())
+}
+
 fn create_launch_tab(
     config: Rc<RefCell<Config>>,
     console: TextView,
@@ -476,33 +978,256 @@ fn create_launch_tab(
     vbox
 }
 
-fn setup_progress_receiver(rx: mpsc::Receiver<String>, console: TextView, button: Option<Button>) {
-    glib::idle_add_local(move || match rx.try_recv() {
-        Ok(msg) => {
-            if msg == "DONE" {
-                if let Some(button) = &button {
-                    button.set_sensitive(true);
+/// The Cancel buttons of the tabs and the token of the task they stop.
+/// Only one task runs at a time, whichever tab started it: the buttons that
+/// start one stay disabled until it is over.
+#[derive(Clone)]
+struct CancelControl {
+    token: Rc<RefCell<CancelToken>>,
+    running: Rc<Cell<bool>>,
+    cancel_buttons: Rc<RefCell<Vec<Button>>>,
+    task_buttons: Rc<RefCell<Vec<Button>>>,
+}
+
+impl CancelControl {
+    fn new() -> Self {
+        CancelControl {
+            token: Rc::new(RefCell::new(CancelToken::new())),
+            running: Rc::new(Cell::new(false)),
+            cancel_buttons: Rc::new(RefCell::new(Vec::new())),
+            task_buttons: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    /// A Cancel button for a tab, enabled while a task that can be
+    /// cancelled runs.
+    fn cancel_button(&self) -> Button {
+        let button = Button::with_label("Cancel");
+        button.set_sensitive(false);
+        let token = self.token.clone();
+        let cancel_buttons = self.cancel_buttons.clone();
+        button.connect_clicked(move |_| {
+            token.borrow().cancel();
+            for button in cancel_buttons.borrow().iter() {
+                button.set_sensitive(false);
+            }
+        });
+        self.cancel_buttons.borrow_mut().push(button.clone());
+        button
+    }
+
+    /// Registers a button that starts a task, to be disabled while any
+    /// task runs.
+    fn guard(&self, button: &Button) {
+        button.set_sensitive(!self.running.get());
+        self.task_buttons.borrow_mut().push(button.clone());
+    }
+
+    /// Locks the window for a task that cannot be cancelled. False if
+    /// another task is still running.
+    fn lock(&self) -> bool {
+        if self.running.replace(true) {
+            return false;
+        }
+        for button in self.task_buttons.borrow().iter() {
+            button.set_sensitive(false);
+        }
+        true
+    }
+
+    /// Locks the window for a new task and returns the task's token, or
+    /// None if another task is still running.
+    fn start(&self) -> Option<CancelToken> {
+        if !self.lock() {
+            return None;
+        }
+        let token = CancelToken::new();
+        *self.token.borrow_mut() = token.clone();
+        for button in self.cancel_buttons.borrow().iter() {
+            button.set_sensitive(true);
+        }
+        Some(token)
+    }
+
+    fn finish(&self) {
+        self.running.set(false);
+        for button in self.cancel_buttons.borrow().iter() {
+            button.set_sensitive(false);
+        }
+        for button in self.task_buttons.borrow().iter() {
+            button.set_sensitive(true);
+        }
+    }
+}
+
+/// Formats an error for the console, followed by what to do about it.
+fn describe_error(context: &str, error: &PatcherError) -> String {
+    match error.hint() {
+        Some(hint) => format!("{}: {}\n💡 {}", context, error, hint),
+        None => format!("{}: {}", context, error),
+    }
+}
+
+/// Shows the events of a background task in the console and progress bar.
+/// Once the task is over, `on_done` gets its outcome, or `None` if it ended
+/// without reporting one.
+fn setup_progress_receiver<D>(
+    rx: mpsc::Receiver<ProgressEvent>,
+    console: TextView,
+    progress: ProgressBar,
+    on_done: D,
+) where
+    D: FnOnce(Option<Result<String, PatcherError>>) + 'static,
+{
+    let mut on_done = Some(on_done);
+    let mut stage = None;
+    glib::idle_add_local(move || {
+        let event = match rx.try_recv() {
+            Ok(event) => event,
+            Err(mpsc::TryRecvError::Empty) => return glib::ControlFlow::Continue,
+            Err(mpsc::TryRecvError::Disconnected) => {
+                progress.set_visible(false);
+                if let Some(on_done) = on_done.take() {
+                    on_done(None);
                 }
                 return glib::ControlFlow::Break;
-            } else {
-                log_to_console(&console, &msg);
             }
-            glib::ControlFlow::Continue
-        }
-        Err(mpsc::TryRecvError::Empty) => glib::ControlFlow::Continue,
-        Err(mpsc::TryRecvError::Disconnected) => {
-            if let Some(button) = &button {
-                button.set_sensitive(true);
+        };
+
+        match event {
+            ProgressEvent::StageStarted(started) => {
+                stage = Some(started);
+                progress.set_fraction(0.0);
+                progress.set_text(Some(&started.to_string()));
+                progress.set_visible(true);
+            }
+            ProgressEvent::StageFinished(finished) => {
+                if stage == Some(finished) {
+                    progress.set_fraction(1.0);
+                }
+            }
+            ProgressEvent::Bytes { done, total } if total > 0 => {
+                let fraction = (done as f64 / total as f64).min(1.0);
+                progress.set_fraction(fraction);
+                if let Some(stage) = stage {
+                    progress.set_text(Some(&format!("{} {:.0}%", stage, fraction * 100.0)));
+                }
+            }
+            ProgressEvent::Bytes { .. } => progress.pulse(),
+            ProgressEvent::Transfer {
+                done,
+                total,
+                rate,
+                eta,
+            } => {
+                let fraction = if total > 0 {
+                    (done as f64 / total as f64).min(1.0)
+                } else {
+                    0.0
+                };
+                progress.set_fraction(fraction);
+                let mut text = format!(
+                    "{:.0}% · {} of {} · {}/s",
+                    fraction * 100.0,
+                    format_size(done),
+                    format_size(total),
+                    format_size(rate)
+                );
+                if let Some(eta) = eta {
+                    text.push_str(&format!(" · {} left", progress::format_duration(eta)));
+                }
+                if let Some(stage) = stage {
+                    text = format!("{} {}", stage, text);
+                }
+                progress.set_text(Some(&text));
+            }
+            ProgressEvent::Log { level, message } => match level {
+                LogLevel::Info => log_to_console(&console, &message),
+                LogLevel::Success => log_to_console(&console, &format!("✅ {}", message)),
+                LogLevel::Error => log_to_console(&console, &format!("❌ {}", message)),
+            },
+            ProgressEvent::Warning(message) => {
+                log_to_console(&console, &format!("⚠ {}", message));
+            }
+            ProgressEvent::Finished(result) => {
+                progress.set_visible(false);
+                if let Some(on_done) = on_done.take() {
+                    on_done(Some(result));
+                }
+                return glib::ControlFlow::Break;
             }
-            glib::ControlFlow::Break
         }
+        glib::ControlFlow::Continue
     });
 }
 
+/// Logs how a task ended. Returns the version it left the game at.
+fn log_outcome(
+    console: &TextView,
+    outcome: Option<Result<String, PatcherError>>,
+    task: &str,
+) -> Option<String> {
+    match outcome? {
+        Ok(version) => {
+            log_to_console(console, &format!("{} completed successfully!", task));
+            Some(version)
+        }
+        Err(PatcherError::Cancelled) => {
+            log_to_console(console, &format!("{} cancelled.", task));
+            None
+        }
+        Err(e) => {
+            let context = if e.is_cancelled() {
+                format!("{} cancelled", task)
+            } else {
+                format!("{} failed", task)
+            };
+            log_to_console(console, &describe_error(&context, &e));
+            None
+        }
+    }
+}
+
+fn set_installed_version(config: &Rc<RefCell<Config>>, label: &Label, version: &str) {
+    config.borrow_mut().installed_version = version.to_string();
+    if let Err(e) = config.borrow().save() {
+        eprintln!("Failed to save config: {}", e);
+    }
+    label.set_text(&installed_version_text(version));
+}
+
+/// Records `version` as installed if `game_dir` holds the game the Patch
+/// tab is set to, so its next update starts from the right version.
+fn record_if_patched_game(
+    config: &Rc<RefCell<Config>>,
+    label: &Label,
+    game_dir: &str,
+    version: &str,
+) {
+    let canonical = |path: &Path| path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    let votv_exe = PathBuf::from(&config.borrow().votv_exe_path);
+    let is_patched_game = votv_exe
+        .parent()
+        .is_some_and(|dir| canonical(dir) == canonical(Path::new(game_dir)));
+    if is_patched_game {
+        set_installed_version(config, label, version);
+    }
+}
+
+fn installed_version_text(version: &str) -> String {
+    if version.is_empty() {
+        "Installed version: unknown".to_string()
+    } else {
+        format!("Installed version: {}", version)
+    }
+}
+
 fn create_settings_tab(
     config: Rc<RefCell<Config>>,
     console: TextView,
+    progress: ProgressBar,
     window: ApplicationWindow,
+    cancel: CancelControl,
 ) -> GtkBox {
     let vbox = GtkBox::new(Orientation::Vertical, 15);
     vbox.set_margin_top(15);
@@ -534,6 +1259,167 @@ fn create_settings_tab(
     steam_box.append(&browse_steam_btn);
     vbox.append(&steam_box);
 
+    // Manifest signing
+    let key_label = Label::new(Some("Manifest Signing Key (minisign)"));
+    key_label.set_halign(gtk4::Align::Start);
+    key_label.set_margin_top(15);
+    vbox.append(&key_label);
+
+    let key_entry = Entry::new();
+    key_entry.set_placeholder_text(Some("Leave empty to use the built-in key"));
+    key_entry.set_text(&config.borrow().manifest_public_key);
+    vbox.append(&key_entry);
+
+    let unsigned_check = CheckButton::with_label("Allow unsigned manifests (custom mirrors only)");
+    unsigned_check.set_active(config.borrow().allow_unsigned_manifests);
+    vbox.append(&unsigned_check);
+
+    // Download cache
+    let cache_label = Label::new(Some("Download Cache"));
+    cache_label.set_halign(gtk4::Align::Start);
+    cache_label.set_margin_top(15);
+    vbox.append(&cache_label);
+
+    let cache_box = GtkBox::new(Orientation::Horizontal, 10);
+    cache_box.append(&Label::new(Some("Size limit (MB):")));
+    let cache_limit_spin = SpinButton::with_range(0.0, 1_000_000.0, 256.0);
+    cache_limit_spin.set_value(config.borrow().download_cache_limit_mb as f64);
+    cache_box.append(&cache_limit_spin);
+
+    let cache_usage_label = Label::new(Some(&cache_usage_text(
+        DownloadCache::new(&config.borrow()).usage(),
+    )));
+    cache_usage_label.set_hexpand(true);
+    cache_usage_label.set_halign(gtk4::Align::Start);
+    cache_box.append(&cache_usage_label);
+
+    let clear_cache_btn = Button::with_label("Clear Cache");
+    cancel.guard(&clear_cache_btn);
+    let console_clone = console.clone();
+    let window_clone = window.clone();
+    let usage_clone = cache_usage_label.clone();
+    let config_clone = config.clone();
+    clear_cache_btn.connect_clicked(move |_| {
+        let cache = DownloadCache::new(&config_clone.borrow());
+        match cache.clear() {
+            Ok(cleared) => log_to_console(
+                &console_clone,
+                &format!(
+                    "✅ Cleared the download cache ({} files, {})",
+                    cleared.files,
+                    format_size(cleared.bytes)
+                ),
+            ),
+            Err(e) => show_error(&window_clone, &format!("Failed to clear cache: {}", e)),
+        }
+        usage_clone.set_text(&cache_usage_text(cache.usage()));
+    });
+    cache_box.append(&clear_cache_btn);
+    vbox.append(&cache_box);
+
+    // Chunk cache
+    let chunk_label = Label::new(Some("Chunk Cache"));
+    chunk_label.set_halign(gtk4::Align::Start);
+    chunk_label.set_margin_top(15);
+    vbox.append(&chunk_label);
+
+    let chunk_dir_box = GtkBox::new(Orientation::Horizontal, 10);
+    let chunk_dir_entry = Entry::new();
+    chunk_dir_entry.set_placeholder_text(Some("Leave empty to fetch every chunk from the store"));
+    chunk_dir_entry.set_text(&config.borrow().chunk_cache_dir);
+    chunk_dir_entry.set_hexpand(true);
+    chunk_dir_box.append(&chunk_dir_entry);
+
+    let browse_chunk_btn = Button::with_label("Browse...");
+    let window_clone = window.clone();
+    let entry_clone = chunk_dir_entry.clone();
+    browse_chunk_btn.connect_clicked(move |_| {
+        browse_folder(&window_clone, &entry_clone, "Select Chunk Cache Directory");
+    });
+    chunk_dir_box.append(&browse_chunk_btn);
+    vbox.append(&chunk_dir_box);
+
+    let chunk_box = GtkBox::new(Orientation::Horizontal, 10);
+    chunk_box.append(&Label::new(Some("Size limit (MB):")));
+    let chunk_limit_spin = SpinButton::with_range(0.0, 1_000_000.0, 1024.0);
+    chunk_limit_spin.set_value(config.borrow().chunk_cache_limit_mb as f64);
+    chunk_box.append(&chunk_limit_spin);
+
+    let chunk_usage_label = Label::new(None);
+    show_chunk_cache_usage(&chunk_usage_label, &config.borrow());
+    chunk_usage_label.set_hexpand(true);
+    chunk_usage_label.set_halign(gtk4::Align::Start);
+    chunk_box.append(&chunk_usage_label);
+
+    let prune_btn = Button::with_label("Prune");
+    prune_btn.set_tooltip_text(Some(
+        "Delete the chunks none of the installs GYeet knows about uses",
+    ));
+    cancel.guard(&prune_btn);
+    let console_clone = console.clone();
+    let progress_clone = progress.clone();
+    let window_clone = window.clone();
+    let usage_clone = chunk_usage_label.clone();
+    let config_clone = config.clone();
+    let cancel_clone = cancel.clone();
+    prune_btn.connect_clicked(move |_| {
+        let config = config_clone.borrow().clone();
+        if config.chunk_cache_dir.is_empty() {
+            return;
+        }
+        let Some(token) = cancel_clone.start() else {
+            return;
+        };
+        log_to_console(&console_clone, "🔄 Pruning the chunk cache...");
+
+        let (tx, rx) = mpsc::channel::<ProgressEvent>();
+        let (result_tx, result_rx) = mpsc::channel::<Result<CacheUsage, PatcherError>>();
+        let config_for_thread = config.clone();
+        std::thread::spawn(move || {
+            let patcher = Patcher::new(config_for_thread);
+            let result = patcher.prune_chunk_cache(&token, |event| {
+                let _ = tx.send(event);
+            });
+            let _ = result_tx.send(result);
+        });
+        setup_progress_receiver(rx, console_clone.clone(), progress_clone.clone(), |_| {});
+
+        let console = console_clone.clone();
+        let window = window_clone.clone();
+        let usage_label = usage_clone.clone();
+        let cancel = cancel_clone.clone();
+        glib::idle_add_local(move || {
+            // Disconnected without a result if the worker panicked; the
+            // window is unlocked all the same
+            let result = match result_rx.try_recv() {
+                Ok(result) => Some(result),
+                Err(mpsc::TryRecvError::Empty) => return glib::ControlFlow::Continue,
+                Err(mpsc::TryRecvError::Disconnected) => None,
+            };
+            cancel.finish();
+            match result {
+                Some(Ok(pruned)) => log_to_console(
+                    &console,
+                    &format!(
+                        "✅ Pruned the chunk cache ({} chunks, {})",
+                        pruned.files,
+                        format_size(pruned.bytes)
+                    ),
+                ),
+                Some(Err(e)) if e.is_cancelled() => log_to_console(&console, "Prune cancelled."),
+                Some(Err(e)) => {
+                    log_to_console(&console, &describe_error("❌ Failed to prune cache", &e));
+                    show_error(&window, &format!("Failed to prune cache: {}", e));
+                }
+                None => log_to_console(&console, "❌ Pruning stopped unexpectedly"),
+            }
+            show_chunk_cache_usage(&usage_label, &config);
+            glib::ControlFlow::Break
+        });
+    });
+    chunk_box.append(&prune_btn);
+    vbox.append(&chunk_box);
+
     // Save settings button
     let save_btn = Button::with_label("Save Settings");
     save_btn.add_css_class("primary-button");
@@ -543,9 +1429,21 @@ fn create_settings_tab(
     let console_clone = console.clone();
     let window_clone = window.clone();
     let steam_clone = steam_entry.clone();
+    let key_clone = key_entry.clone();
+    let unsigned_clone = unsigned_check.clone();
+    let cache_limit_clone = cache_limit_spin.clone();
+    let chunk_dir_clone = chunk_dir_entry.clone();
+    let chunk_limit_clone = chunk_limit_spin.clone();
+    let chunk_usage_clone = chunk_usage_label.clone();
     save_btn.connect_clicked(move |_| {
         let mut cfg = config_clone.borrow_mut();
         cfg.steam_path = steam_clone.text().to_string();
+        cfg.manifest_public_key = key_clone.text().trim().to_string();
+        cfg.allow_unsigned_manifests = unsigned_clone.is_active();
+        cfg.download_cache_limit_mb = cache_limit_clone.value() as u64;
+        cfg.chunk_cache_dir = chunk_dir_clone.text().trim().to_string();
+        cfg.chunk_cache_limit_mb = chunk_limit_clone.value() as u64;
+        show_chunk_cache_usage(&chunk_usage_clone, &cfg);
 
         match cfg.save() {
             Ok(_) => {
@@ -566,6 +1464,41 @@ fn create_settings_tab(
     vbox
 }
 
+fn cache_usage_text(usage: CacheUsage) -> String {
+    format!("{} files, {}", usage.files, format_size(usage.bytes))
+}
+
+/// Shows the chunk cache's usage in `label`. Counting means reading the
+/// metadata of every chunk, so it happens on another thread.
+fn show_chunk_cache_usage(label: &Label, config: &Config) {
+    let Some(chunk_cache) = ChunkCache::new(config) else {
+        label.set_text("Disabled");
+        return;
+    };
+    label.set_text("Counting...");
+
+    let (tx, rx) = mpsc::channel::<CacheUsage>();
+    std::thread::spawn(move || {
+        let _ = tx.send(chunk_cache.usage());
+    });
+    let label = label.clone();
+    glib::idle_add_local(move || match rx.try_recv() {
+        Ok(usage) => {
+            label.set_text(&format!(
+                "{} chunks, {}",
+                usage.files,
+                format_size(usage.bytes)
+            ));
+            glib::ControlFlow::Break
+        }
+        Err(mpsc::TryRecvError::Empty) => glib::ControlFlow::Continue,
+        Err(mpsc::TryRecvError::Disconnected) => {
+            label.set_text("Unknown");
+            glib::ControlFlow::Break
+        }
+    });
+}
+
 // Helper functions
 fn browse_file(window: &ApplicationWindow, entry: &Entry, title: &str, _pattern: &str) {
     let dialog = FileChooserDialog::builder()
@@ -651,6 +1584,24 @@ fn show_info(window: &ApplicationWindow, message: &str) {
     dialog.show();
 }
 
+fn confirm<F>(window: &ApplicationWindow, message: &str, on_response: F)
+where
+    F: Fn(bool) + 'static,
+{
+    let dialog = MessageDialog::new(
+        Some(window),
+        gtk4::DialogFlags::MODAL,
+        MessageType::Question,
+        ButtonsType::YesNo,
+        message,
+    );
+    dialog.connect_response(move |dialog, response| {
+        dialog.close();
+        on_response(response == ResponseType::Yes);
+    });
+    dialog.show();
+}
+
 fn apply_css() {
     let provider = gtk4::CssProvider::new();
     provider.load_from_data(